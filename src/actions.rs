@@ -1,28 +1,84 @@
 use std::env::current_dir;
 
 use crate::actions::rotate::RotateDirection;
+use crate::actions::stacks::StacksAction;
+use crate::backend::{discover, BackendKind};
 use crate::errors::Result;
-use crate::stack::FileStack;
+use crate::git::check_repo_state;
+use crate::intent::{Intent, SwitchOptions};
+use crate::journal::Journal;
+use crate::stacks::{current_stack_name, open_stack, StackScope};
+use crate::stash::DirtyPolicy;
+use crate::submodules::recurse_from_config;
 
 /// The actions that we can take on a branch stack, along with any
 /// parameters they need.
 #[derive(Debug)]
 pub enum Action {
     /// Push a branch onto the stack, along with a branch name, and change
-    /// into that branch.
-    Push(String),
-    /// List the stack.
-    List,
+    /// into that branch. The name is the raw bytes of the argument.
+    Push(Vec<u8>),
+    /// Create a new branch, starting at HEAD or at the revision given, and
+    /// push it.
+    PushNew {
+        branch: String,
+        start_point: Option<String>,
+    },
+    /// Create a temporary branch at a revision and push it. Popping back
+    /// off it deletes the branch.
+    PushTemp(String),
+    /// List the stack. If `verbose` is set, this also shows which stack
+    /// is being listed and where it's stored.
+    List { verbose: bool },
     /// Remove a branch from the stack and change into the nexi one down.
     Pop,
     /// Take an item from the middle of the stack and rotate it to the top.
     Rotate(RotateDirection, usize),
+    /// Reverse the last operation recorded in the journal.
+    Undo,
+    /// Re-apply the last operation that was undone.
+    Redo,
+    /// Finish an operation that was interrupted.
+    Continue,
+    /// Roll back an operation that was interrupted.
+    Abort,
+    /// Manage the named stacks.
+    Stacks(StacksAction),
+}
+
+/// Options that apply to every action.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// The name of the stack to use. If this is `None`, the current stack
+    /// remembered in the git config is used.
+    pub stack: Option<String>,
+    /// Where to look for stacks. If this is `None`, the scope is taken from
+    /// the git config.
+    pub scope: Option<StackScope>,
+    /// What to do with uncommitted changes when switching branches. If this
+    /// is `None`, the policy is taken from the git config.
+    pub dirty: Option<DirtyPolicy>,
+    /// Whether to offer to discard local changes that would be overwritten
+    /// by switching branches.
+    pub force: bool,
+    /// Whether to check out branches that are checked out in other
+    /// worktrees.
+    pub ignore_other_worktrees: bool,
+    /// Whether to update submodules after switching branches. If this is
+    /// `None`, it's taken from the git config.
+    pub recurse_submodules: Option<bool>,
+    /// Which backend checks out branches. If this is `None`, it's taken
+    /// from the git config.
+    pub backend: Option<BackendKind>,
 }
 
 pub mod list;
 pub mod pop;
 pub mod push;
+pub mod recover;
 pub mod rotate;
+pub mod stacks;
+pub mod undo;
 
 use Action::*;
 
@@ -30,16 +86,70 @@ use Action::*;
 /// of its porents.
 ///
 /// This also creates resources used by all of the cammands, like the
-/// Repository and the FlieStack.
-pub fn invoke_action(action: Action) -> Result<()> {
+/// backend and the FlieStack.
+///
+/// If an earlier operation was interrupted, only `list`, `stacks`,
+/// `continue`, and `abort` are allowed until it's been dealt with. Actions
+/// that switch branches also refuse to run in the middle of a merge,
+/// rebase, or the like.
+pub fn invoke_action(action: Action, options: &Options) -> Result<()> {
     let cwd = current_dir()?;
-    let repo = git2::Repository::discover(&cwd)?;
-    let mut stack = FileStack::new(&repo.path().join("BRANCH_STACK"))?;
+    let repo = discover(&cwd, options.backend)?;
+    let repo = &*repo;
+
+    let scope = match options.scope {
+        Some(scope) => scope,
+        None => StackScope::from_config(repo)?,
+    };
+
+    match action {
+        Continue => return recover::continue_operation(repo),
+        Abort => return recover::abort_operation(repo),
+        Stacks(ref stacks_action) => return stacks::manage_stacks(repo, scope, stacks_action),
+        List { .. } => {}
+        _ => {
+            Intent::check(repo)?;
+            check_repo_state(repo)?;
+        }
+    }
+
+    let stack_name = match options.stack {
+        Some(ref name) => name.clone(),
+        None => current_stack_name(repo, scope)?,
+    };
+    let mut stack = open_stack(repo, scope, &stack_name)?;
+    let journal = Journal::for_stack(stack.path());
+    let switch_options = SwitchOptions {
+        dirty: match options.dirty {
+            Some(dirty) => dirty,
+            None => DirtyPolicy::from_config(repo)?,
+        },
+        force: options.force,
+        ignore_other_worktrees: options.ignore_other_worktrees,
+        recurse_submodules: match options.recurse_submodules {
+            Some(recurse) => recurse,
+            None => recurse_from_config(repo)?,
+        },
+    };
 
+    let (stack, journal, opts) = (&mut stack, &journal, &switch_options);
     match action {
-        Push(ref branch_name) => push::push_branch(&repo, &mut stack, branch_name),
-        List => list::list_branch_stack(&repo, &stack),
-        Pop => pop::pop_branch_stack(&repo, &mut stack),
-        Rotate(d, n) => rotate::rotate_branch(&repo, &mut stack, d, n),
+        Push(ref branch_name) => push::push_branch_bytes(repo, stack, journal, opts, branch_name),
+        PushNew {
+            ref branch,
+            ref start_point,
+        } => push::push_new_branch(repo, stack, journal, opts, branch, start_point.as_deref()),
+        PushTemp(ref revision) => push::push_temp_branch(repo, stack, journal, opts, revision),
+        List { verbose } => {
+            if verbose {
+                list::describe_stack(&stack_name, scope, stack);
+            }
+            list::list_branch_stack(repo, stack)
+        }
+        Pop => pop::pop_branch_stack(repo, stack, journal, opts),
+        Rotate(d, n) => rotate::rotate_branch(repo, stack, journal, opts, d, n),
+        Undo => undo::undo(repo, stack, journal, opts),
+        Redo => undo::redo(repo, stack, journal, opts),
+        Continue | Abort | Stacks(_) => unreachable!(),
     }
 }
@@ -1,20 +1,31 @@
 /// # List Command
 ///
-/// This executes the `list` command. It prints the current branch name as
-/// well as the stack.
-use git2::Repository;
-
+/// This executes the `list` command. It prints the current branch name, or
+/// the commit if HEAD is detached, as well as the stack. In verbose mode, it
+/// first prints which stack it's listing and where that stack is stored.
+use crate::backend::GitBackend;
 use crate::errors::Result;
-use crate::git::get_current_branch_name;
-use crate::stack::FileStack;
+use crate::git::get_head_entry;
+use crate::stack::{Stack, Storage};
+use crate::stacks::StackScope;
 
-pub fn list_branch_stack(repo: &Repository, stack: &FileStack) -> Result<()> {
-    let branch_name = get_current_branch_name(&repo)?;
+pub fn list_branch_stack<S: Stack>(backend: &dyn GitBackend, stack: &S) -> Result<()> {
+    let head = get_head_entry(backend)?;
 
-    println!("{}", branch_name);
-    for branch_name in stack.iter() {
-        println!("{}", branch_name);
+    println!("{}", head);
+    for entry in stack.iter() {
+        println!("{}", entry);
     }
 
     Ok(())
 }
+
+pub fn describe_stack<S: Stack>(name: &str, scope: StackScope, stack: &S) {
+    match stack.location() {
+        Some((path, Storage::File)) => println!("# stack {} ({}: {})", name, scope, path.display()),
+        Some((_, Storage::Ref { ref refname, .. })) => {
+            println!("# stack {} (ref: {})", name, refname)
+        }
+        None => println!("# stack {} (memory)", name),
+    }
+}
@@ -1,18 +1,56 @@
 /// # Pop Command
 ///
 /// This implements the `pop` command.
-use git2::Repository;
-
+///
+/// If the entry that's popped was pushed with `push --temp`, the temporary
+/// branch is deleted once it's been left, as long as every commit on it can
+/// still be reached from some other ref. Otherwise it's kept, and a warning
+/// says so.
+use crate::backend::GitBackend;
 use crate::errors::{BranchStackError, Result};
-use crate::git::change_branch;
-use crate::stack::FileStack;
+use crate::git::is_reachable_elsewhere;
+use crate::intent::{switch, SwitchOptions};
+use crate::journal::{Journal, Record};
+use crate::stack::Stack;
+
+pub fn pop_branch_stack<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+) -> Result<()> {
+    let entry = switch(
+        repo,
+        stack,
+        journal,
+        options,
+        "pop",
+        Record::Apply,
+        |stack, _| stack.pop().ok_or(BranchStackError::EmptyStack),
+    )?;
+    println!("{}", entry);
+    if let Some(ref temp_branch) = entry.temp_branch {
+        delete_temp_branch(repo, temp_branch)?;
+    }
+    Ok(())
+}
 
-pub fn pop_branch_stack(repo: &Repository, stack: &mut FileStack) -> Result<()> {
-    stack
-        .pop()
-        .ok_or(BranchStackError::EmptyStack)
-        .and_then(|branch_name| {
-            println!("{}", branch_name);
-            change_branch(repo, &branch_name)
-        })
+fn delete_temp_branch(repo: &dyn GitBackend, branch_name: &str) -> Result<()> {
+    let refname = format!("refs/heads/{}", branch_name);
+    if repo.find_ref(refname.as_bytes())?.is_none() {
+        return Ok(());
+    }
+    if repo.current_branch_name().ok().as_deref() == Some(branch_name.as_bytes()) {
+        return Ok(());
+    }
+    if is_reachable_elsewhere(repo, branch_name)? {
+        repo.delete_branch(branch_name)?;
+        eprintln!("deleted temporary branch {}", branch_name);
+    } else {
+        eprintln!(
+            "kept temporary branch {}: it has commits that aren't on any other branch",
+            branch_name
+        );
+    }
+    Ok(())
 }
@@ -1,15 +1,159 @@
 /// # Push command
 ///
 /// This implements the `push` command.
-use git2::Repository;
+///
+/// If there's no local branch, tag, or revision with the name given, but
+/// one remote has a branch with that name, a local branch is created to
+/// track it, the same as `git switch` does. With `-c` (or `-b`), a new
+/// branch is created at HEAD or at a start point, like `git switch -c`.
+/// With `--temp`, a temporary branch is created for looking around, and
+/// it's deleted again by the `pop` that leaves it. In all of these cases,
+/// if the switch fails before the new branch is checked out, it's deleted
+/// right away.
+use std::str;
+
+use crate::backend::GitBackend;
+use crate::errors::{BranchStackError, Result};
+use crate::git::{
+    create_branch, find_entry, find_entry_bytes, remote_branch_to_track, temp_branch_name,
+};
+use crate::intent::{switch, SwitchOptions};
+use crate::journal::{Journal, Record};
+use crate::stack::{EntryKind, Stack, StackEntry};
+
+pub fn push_branch<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+    branch_name: &str,
+) -> Result<()> {
+    push_branch_bytes(repo, stack, journal, options, branch_name.as_bytes())
+}
 
-use crate::errors::Result;
-use crate::git::{change_branch, get_current_branch_name};
-use crate::stack::FileStack;
+/// Push the branch, tag, or revision named by the bytes `branch_name`. A
+/// name that isn't valid UTF-8 has to be a ref that's already there.
+pub fn push_branch_bytes<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+    branch_name: &[u8],
+) -> Result<()> {
+    let action = format!("push {}", String::from_utf8_lossy(branch_name));
+    match (
+        find_entry_bytes(repo, branch_name),
+        str::from_utf8(branch_name),
+    ) {
+        (Err(BranchStackError::InvalidRevision(_)), Ok(branch_name)) => {
+            let start = remote_branch_to_track(repo, branch_name)?;
+            let target = create_branch_at(repo, branch_name, &start)?;
+            let result = push_entry(repo, stack, journal, options, &action, target, false);
+            discard_on_failure(repo, branch_name, result)
+        }
+        (target, _) => push_entry(repo, stack, journal, options, &action, target?, false),
+    }
+}
+
+/// Create the branch `branch_name` at `start_point`, or at HEAD if there
+/// isn't one, and push it.
+pub fn push_new_branch<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+    branch_name: &str,
+    start_point: Option<&str>,
+) -> Result<()> {
+    let start = find_entry(repo, start_point.unwrap_or("HEAD"))?;
+    let target = create_branch_at(repo, branch_name, &start)?;
+    let action = match start_point {
+        Some(start_point) => format!("push -c {} {}", branch_name, start_point),
+        None => format!("push -c {}", branch_name),
+    };
+    let result = push_entry(repo, stack, journal, options, &action, target, false);
+    discard_on_failure(repo, branch_name, result)
+}
 
-pub fn push_branch(repo: &Repository, stack: &mut FileStack, branch_name: &String) -> Result<()> {
-    let current_branch = get_current_branch_name(&repo)?;
-    change_branch(&repo, branch_name)?;
-    stack.push(current_branch);
+/// Create a temporary branch at `revision` and push it. The entry that's
+/// pushed is marked with the branch's name, so popping back to it deletes
+/// the branch.
+pub fn push_temp_branch<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+    revision: &str,
+) -> Result<()> {
+    let start = find_entry(repo, revision)?;
+    let branch_name = temp_branch_name(repo, &start)?;
+    let target = create_branch_at(repo, &branch_name, &start)?;
+    let action = format!("push --temp {}", revision);
+    let result = push_entry(repo, stack, journal, options, &action, target, true);
+    discard_on_failure(repo, &branch_name, result)
+}
+
+/// Create the branch `branch_name` at `start`, and say so if it tracks a
+/// remote-tracking branch, the way `git branch` does.
+fn create_branch_at(
+    repo: &dyn GitBackend,
+    branch_name: &str,
+    start: &StackEntry,
+) -> Result<StackEntry> {
+    let target = create_branch(repo, branch_name, start)?;
+    if start.kind == EntryKind::Remote {
+        println!("branch '{}' set up to track '{}'.", branch_name, start.name);
+    }
+    Ok(target)
+}
+
+/// Push the current entry onto the stack and check out `target`. If
+/// `temp` is set, `target` is a temporary branch, and the current entry is
+/// marked with its name.
+fn push_entry<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+    action: &str,
+    target: StackEntry,
+    temp: bool,
+) -> Result<()> {
+    switch(
+        repo,
+        stack,
+        journal,
+        options,
+        action,
+        Record::Apply,
+        |stack, current_entry| {
+            stack.push(if temp {
+                current_entry.with_temp_branch(target.name.clone())
+            } else {
+                current_entry
+            });
+            Ok(target)
+        },
+    )?;
     Ok(())
 }
+
+/// Delete `branch_name`, which was just created for a push, if the push
+/// failed before it was checked out. The push's own error is what's
+/// returned; if the branch can't be deleted, that's only reported.
+fn discard_on_failure(repo: &dyn GitBackend, branch_name: &str, result: Result<()>) -> Result<()> {
+    if result.is_err() {
+        if let Err(err) = delete_unused_branch(repo, branch_name) {
+            eprintln!("warning: couldn't delete branch {}: {}", branch_name, err);
+        }
+    }
+    result
+}
+
+/// Delete the local branch `branch_name` unless it's checked out.
+fn delete_unused_branch(repo: &dyn GitBackend, branch_name: &str) -> Result<()> {
+    if repo.current_branch_name().ok().as_deref() == Some(branch_name.as_bytes()) {
+        return Ok(());
+    }
+    repo.delete_branch(branch_name)
+}
@@ -0,0 +1,19 @@
+/// # Continue and Abort Commands
+///
+/// These finish or roll back an operation that was interrupted between
+/// checking out a branch and saving the stack.
+use crate::backend::GitBackend;
+use crate::errors::Result;
+use crate::intent::{abort_intent, continue_intent};
+
+pub fn continue_operation(repo: &dyn GitBackend) -> Result<()> {
+    let entry = continue_intent(repo)?;
+    println!("{}", entry);
+    Ok(())
+}
+
+pub fn abort_operation(repo: &dyn GitBackend) -> Result<()> {
+    let entry = abort_intent(repo)?;
+    println!("{}", entry);
+    Ok(())
+}
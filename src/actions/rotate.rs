@@ -1,34 +1,47 @@
-use git2::Repository;
-
+use crate::backend::GitBackend;
 use crate::errors::{BranchStackError, Result};
-use crate::git::{change_branch, get_current_branch_name};
-use crate::stack::FileStack;
+use crate::intent::{switch, SwitchOptions};
+use crate::journal::{Journal, Record};
+use crate::stack::Stack;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum RotateDirection {
     Up,
     Down,
 }
 
-pub fn rotate_branch(
-    repo: &Repository,
-    stack: &mut FileStack,
+pub fn rotate_branch<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
     dir: RotateDirection,
     n: usize,
 ) -> Result<()> {
-    let current_branch = get_current_branch_name(repo)?;
-    stack.push(current_branch);
+    let action = match dir {
+        RotateDirection::Up => format!("push +{}", n),
+        RotateDirection::Down => format!("push -{}", n),
+    };
+    let new_branch = switch(
+        repo,
+        stack,
+        journal,
+        options,
+        &action,
+        Record::Apply,
+        |stack, current_entry| {
+            stack.push(current_entry);
 
-    eprintln!("pre-rotate: {:?}", stack);
-    match dir {
-        RotateDirection::Up => stack.rotate_up(n)?,
-        RotateDirection::Down => stack.rotate_down(n)?,
-    }
-    eprintln!("post-rotate: {:?}", stack);
+            match dir {
+                RotateDirection::Up => stack.rotate_up(n)?,
+                RotateDirection::Down => stack.rotate_down(n)?,
+            }
 
-    let new_branch = stack.pop().ok_or(BranchStackError::NoStackEntry)?;
+            stack.pop().ok_or(BranchStackError::NoStackEntry)
+        },
+    )?;
     println!("{}", new_branch);
-    change_branch(repo, &new_branch)
+    Ok(())
 }
 
 pub fn parse_rotation(input: &str) -> Option<(RotateDirection, usize)> {
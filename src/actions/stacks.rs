@@ -0,0 +1,42 @@
+/// # Stacks Command
+///
+/// This manages the named stacks in a repository: listing them, creating
+/// and deleting them, and choosing the one to use by default.
+use crate::backend::GitBackend;
+use crate::errors::Result;
+use crate::stacks::{
+    create_stack, current_stack_name, delete_stack, list_stacks, set_current_stack_name, StackScope,
+};
+
+/// The things the `stacks` command can do.
+#[derive(Debug)]
+pub enum StacksAction {
+    /// List the stacks, marking the current one.
+    List,
+    /// Create a new, empty stack.
+    Create(String),
+    /// Delete a stack.
+    Delete(String),
+    /// Make a stack the default.
+    Switch(String),
+}
+
+pub fn manage_stacks(
+    repo: &dyn GitBackend,
+    scope: StackScope,
+    action: &StacksAction,
+) -> Result<()> {
+    match action {
+        StacksAction::List => {
+            let current = current_stack_name(repo, scope)?;
+            for name in list_stacks(repo, scope)? {
+                let marker = if name == current { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+            Ok(())
+        }
+        StacksAction::Create(ref name) => create_stack(repo, scope, name),
+        StacksAction::Delete(ref name) => delete_stack(repo, scope, name),
+        StacksAction::Switch(ref name) => set_current_stack_name(repo, scope, name),
+    }
+}
@@ -0,0 +1,63 @@
+/// # Undo and Redo Commands
+///
+/// These walk backwards and forwards through the operation journal. Undoing
+/// an operation puts back the stack from before it ran and checks out the
+/// branch that was current then. Redoing it puts back the stack and branch
+/// from after it ran.
+use crate::backend::GitBackend;
+use crate::errors::{BranchStackError, Result};
+use crate::intent::{switch, SwitchOptions};
+use crate::journal::{Journal, Record};
+use crate::stack::Stack;
+
+pub fn undo<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+) -> Result<()> {
+    let operation = journal
+        .last_done()?
+        .ok_or(BranchStackError::NothingToUndo)?;
+    let id = operation.id;
+    let branch_name = switch(
+        repo,
+        stack,
+        journal,
+        options,
+        "undo",
+        |_| Record::Undo { id },
+        |stack, _| {
+            stack.replace(operation.before);
+            Ok(operation.head_before)
+        },
+    )?;
+    println!("{}", branch_name);
+    Ok(())
+}
+
+pub fn redo<S: Stack>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+) -> Result<()> {
+    let operation = journal
+        .last_undone()?
+        .ok_or(BranchStackError::NothingToRedo)?;
+    let id = operation.id;
+    let branch_name = switch(
+        repo,
+        stack,
+        journal,
+        options,
+        "redo",
+        |_| Record::Redo { id },
+        |stack, _| {
+            stack.replace(operation.after);
+            Ok(operation.head_after)
+        },
+    )?;
+    println!("{}", branch_name);
+    Ok(())
+}
@@ -0,0 +1,505 @@
+/// # Backends
+///
+/// Everything this program does to a repository goes through a
+/// `GitBackend`, chosen by the `BackendKind`:
+///
+/// - `libgit2` does everything in-process with libgit2. It's built with the
+///   `libgit2` cargo feature, which is on by default, and it's the default
+///   backend whenever it's built in.
+/// - `gix` uses gitoxide. It's built with the `gix` cargo feature, and it's
+///   the default backend when libgit2 isn't built in.
+/// - `git` runs the installed `git` binary to read HEAD and check entries
+///   out. That's slower, but it picks up everything `git checkout` does
+///   that the others don't: hooks, LFS and other process filters,
+///   fsmonitor, and so on. Everything else is handed to the default
+///   backend.
+///
+/// At least one of the `libgit2` and `gix` features has to be enabled.
+/// Building with only `gix` leaves libgit2 out of the program entirely.
+///
+/// The backend is taken from the `--backend` option or the
+/// `branchstack.backend` git config setting.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::errors::{BranchStackError, Result};
+use crate::stack::StackEntry;
+
+mod cli;
+#[cfg(feature = "gix")]
+mod gitoxide;
+#[cfg(feature = "libgit2")]
+mod libgit2;
+
+pub use self::cli::CliBackend;
+#[cfg(feature = "gix")]
+pub use self::gitoxide::GixBackend;
+#[cfg(feature = "libgit2")]
+pub use self::libgit2::Libgit2Backend;
+
+#[cfg(not(any(feature = "libgit2", feature = "gix")))]
+compile_error!("enable the `libgit2` feature, the `gix` feature, or both");
+
+/// The git config setting that chooses the backend.
+const BACKEND_CONFIG_KEY: &str = "branchstack.backend";
+
+/// The operations on a repository. Commits are passed around as the hex
+/// strings of their ids.
+pub trait GitBackend {
+    /// Which backend this is.
+    fn kind(&self) -> BackendKind;
+
+    /// The repository's git directory. In a linked worktree, this is the
+    /// worktree's own one.
+    fn git_dir(&self) -> &Path;
+
+    /// The top of the working tree, or `None` if the repository is bare.
+    fn workdir(&self) -> Option<&Path>;
+
+    /// The name of the branch HEAD is on, as the bytes git has for it. If
+    /// HEAD is detached, this returns `Err(BranchStackError::NoCurrentBranch)`.
+    fn current_branch_name(&self) -> Result<Vec<u8>>;
+
+    /// The id of the commit HEAD points to. If the current branch doesn't
+    /// have any commits yet, this returns `Err(BranchStackError::UnbornHead)`.
+    fn head_commit(&self) -> Result<String>;
+
+    /// Check out the local branch `branch_name`, keeping local changes.
+    fn change_branch(&self, branch_name: &str) -> Result<()>;
+
+    /// Check out a stack entry. Branches put HEAD on the branch; anything
+    /// else leaves HEAD detached at its commit. If `force` is set, local
+    /// changes that are in the way are thrown away.
+    fn checkout_entry(&self, entry: &StackEntry, force: bool) -> Result<()>;
+
+    /// Whether checking out runs git's hooks itself, so they shouldn't be
+    /// run again afterward.
+    fn runs_hooks(&self) -> bool {
+        false
+    }
+
+    /// The commit that the ref `refname`, given as the bytes of its full
+    /// name, points to. If there's no such ref, this returns `None`.
+    fn find_ref(&self, refname: &[u8]) -> Result<Option<String>>;
+
+    /// Each ref whose full name starts with `prefix`, as the bytes of its
+    /// name and the commit it points to. Refs that don't point to a commit
+    /// are left out.
+    fn refs(&self, prefix: &str) -> Result<Vec<(Vec<u8>, String)>>;
+
+    /// The commit that `revision` points to, read the way `git rev-parse`
+    /// would. If `revision` names a ref, rather than something reached from
+    /// one like `master~1`, the ref's full name comes with it. If nothing
+    /// matches, this returns `Err(BranchStackError::InvalidRevision)`.
+    fn resolve_revision(&self, revision: &str) -> Result<(String, Option<Vec<u8>>)>;
+
+    /// Can `ancestor` be reached from `descendant`? A commit can always
+    /// reach itself.
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool>;
+
+    /// The names of the configured remotes.
+    fn remotes(&self) -> Result<Vec<String>>;
+
+    /// Create the local branch `name` at `commit`. If there's already a
+    /// branch called `name`, this returns `Err(BranchStackError::BranchExists)`.
+    fn create_branch(&self, name: &str, commit: &str) -> Result<()>;
+
+    /// Delete the local branch `name` and its config, like `git branch -D`.
+    fn delete_branch(&self, name: &str) -> Result<()>;
+
+    /// Delete the ref `refname`. If there's no such ref, nothing happens.
+    fn delete_ref(&self, refname: &str) -> Result<()>;
+
+    /// The string value of the config setting `key`, or `None` if it isn't
+    /// set.
+    fn config_string(&self, key: &str) -> Result<Option<String>>;
+
+    /// The value of the config setting `key` read as a boolean, or `None`
+    /// if it isn't set.
+    fn config_bool(&self, key: &str) -> Result<Option<bool>>;
+
+    /// Set the config setting `key` to `value` in the repository's own
+    /// config file.
+    fn set_config(&self, key: &str, value: &str) -> Result<()>;
+
+    /// Read the file `filename` from the tree of the commit that `refname`
+    /// points to. If the ref doesn't exist, this returns `None`.
+    fn read_ref_file(&self, refname: &str, filename: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Commit a tree holding only `filename` with `contents` on top of
+    /// `refname`, and move the ref to it. The previous commit becomes the
+    /// parent, so the ref keeps a history of its contents. If nothing
+    /// changed, no commit is made. This returns the commit the ref is at.
+    fn commit_ref_file(
+        &self,
+        refname: &str,
+        filename: &str,
+        contents: &[u8],
+        message: &str,
+    ) -> Result<String>;
+
+    /// Are there changes to tracked files in the index or the working tree?
+    /// Submodules don't count.
+    fn is_dirty(&self) -> Result<bool>;
+
+    /// Stash the uncommitted changes to tracked files with `message`, like
+    /// `git stash push` does, and return the id of the stash commit.
+    fn stash_changes(&self, message: &str) -> Result<String>;
+
+    /// Re-apply the stash with the id `stash` and drop it, like `git stash
+    /// pop` does. If it isn't in the stash list, nothing happens. If it
+    /// can't be applied cleanly, it's kept, and this returns an error.
+    fn pop_stash(&self, stash: &str) -> Result<()>;
+
+    /// The submodules in the index, as the path to each one from the top
+    /// of the working tree and the commit recorded for it.
+    fn submodules(&self) -> Result<Vec<(PathBuf, String)>>;
+
+    /// Fetch from the default remote.
+    fn fetch(&self) -> Result<()>;
+}
+
+/// Which backend to use.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BackendKind {
+    /// libgit2, in-process, if the `libgit2` feature is enabled.
+    Libgit2,
+    /// The installed `git` binary.
+    Cli,
+    /// gitoxide, if the `gix` feature is enabled.
+    Gix,
+}
+
+impl BackendKind {
+    /// The backend set in the git config, or the default one if there
+    /// isn't one.
+    pub fn from_config(repo: &dyn GitBackend) -> Result<BackendKind> {
+        match repo.config_string(BACKEND_CONFIG_KEY)? {
+            Some(backend) => backend.parse(),
+            None => Ok(BackendKind::default()),
+        }
+    }
+}
+
+impl Default for BackendKind {
+    /// libgit2 if it's built in, and gitoxide if it isn't. The `git`
+    /// backend is never the default, since it needs one of the others.
+    fn default() -> BackendKind {
+        if cfg!(feature = "libgit2") {
+            BackendKind::Libgit2
+        } else {
+            BackendKind::Gix
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = BranchStackError;
+
+    fn from_str(input: &str) -> Result<BackendKind> {
+        match input {
+            "libgit2" => Ok(BackendKind::Libgit2),
+            "git" => Ok(BackendKind::Cli),
+            "gix" => Ok(BackendKind::Gix),
+            _ => Err(BranchStackError::InvalidBackend(input.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendKind::Libgit2 => write!(f, "libgit2"),
+            BackendKind::Cli => write!(f, "git"),
+            BackendKind::Gix => write!(f, "gix"),
+        }
+    }
+}
+
+/// Open the repository at `path`, or the one that `path` is in, with the
+/// backend of kind `kind`. If that backend wasn't built in, this returns
+/// `Err(BranchStackError::BackendUnavailable)`.
+pub fn open_backend(path: &Path, kind: BackendKind) -> Result<Box<dyn GitBackend>> {
+    match kind {
+        #[cfg(feature = "libgit2")]
+        BackendKind::Libgit2 => Ok(Box::new(Libgit2Backend::open(path)?)),
+        BackendKind::Cli => Ok(Box::new(CliBackend::open(path)?)),
+        #[cfg(feature = "gix")]
+        BackendKind::Gix => Ok(Box::new(GixBackend::open(path)?)),
+        #[allow(unreachable_patterns)]
+        _ => Err(BranchStackError::BackendUnavailable(kind.to_string())),
+    }
+}
+
+/// Open the repository that `path` is in with the backend `kind`, or with
+/// the one set in its git config if that's `None`.
+pub fn discover(path: &Path, kind: Option<BackendKind>) -> Result<Box<dyn GitBackend>> {
+    let repo = open_backend(path, BackendKind::default())?;
+    let kind = match kind {
+        Some(kind) => kind,
+        None => BackendKind::from_config(&*repo)?,
+    };
+    if kind == repo.kind() {
+        Ok(repo)
+    } else {
+        open_backend(path, kind)
+    }
+}
+
+/// The backends built into this program.
+#[cfg(test)]
+pub fn all_kinds() -> Vec<BackendKind> {
+    let mut kinds = vec![BackendKind::Cli];
+    if cfg!(feature = "libgit2") {
+        kinds.push(BackendKind::Libgit2);
+    }
+    if cfg!(feature = "gix") {
+        kinds.push(BackendKind::Gix);
+    }
+    kinds
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use git2::{Repository, Signature};
+    use spectral::prelude::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::{all_kinds, discover, open_backend, BackendKind};
+    use crate::errors::BranchStackError;
+    use crate::stack::StackEntry;
+
+    fn setup_repo() -> (TempDir, Repository) {
+        let working_dir = tempdir().unwrap();
+        let repo = Repository::init(working_dir.path()).unwrap();
+        let sig = Signature::now("Zaphod Beeblebrox", "zbeebleb@heartofgold.ship").unwrap();
+        {
+            write(working_dir.path().join("file-1"), "first\n").unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path("file-1".as_ref()).unwrap();
+            index.write().unwrap();
+            let oid = index.write_tree().unwrap();
+            let tree = repo.find_tree(oid).unwrap();
+            let commit = repo
+                .commit(Some("HEAD"), &sig, &sig, "initial commit", &tree, &[])
+                .unwrap();
+            let commit = repo.find_commit(commit).unwrap();
+            repo.branch("branch-2", &commit, false).unwrap();
+        }
+        (working_dir, repo)
+    }
+
+    fn head_branch(repo: &Repository) -> Option<String> {
+        let head = repo.head().unwrap();
+        if repo.head_detached().unwrap() {
+            None
+        } else {
+            head.shorthand().map(str::to_string)
+        }
+    }
+
+    #[test]
+    fn test_backend_kind_parses() {
+        assert_that(&"libgit2".parse::<BackendKind>().unwrap()).is_equal_to(BackendKind::Libgit2);
+        assert_that(&"git".parse::<BackendKind>().unwrap()).is_equal_to(BackendKind::Cli);
+        assert_that(&"gix".parse::<BackendKind>().unwrap()).is_equal_to(BackendKind::Gix);
+        assert_that(&"hg".parse::<BackendKind>())
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::InvalidBackend(_)));
+    }
+
+    #[test]
+    fn test_discover_uses_the_configured_backend() {
+        let (working_dir, repo) = setup_repo();
+        let dir = working_dir.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+
+        let backend = discover(&dir, None).unwrap();
+        assert_that(&backend.kind()).is_equal_to(BackendKind::default());
+        assert_that(&backend.workdir().unwrap().canonicalize().unwrap())
+            .is_equal_to(working_dir.path().canonicalize().unwrap());
+
+        repo.config()
+            .unwrap()
+            .set_str("branchstack.backend", "git")
+            .unwrap();
+        assert_that(&discover(&dir, None).unwrap().kind()).is_equal_to(BackendKind::Cli);
+        let backend = discover(&dir, Some(BackendKind::default())).unwrap();
+        assert_that(&backend.kind()).is_equal_to(BackendKind::default());
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        for kind in all_kinds() {
+            let (working_dir, repo) = setup_repo();
+            let backend = open_backend(working_dir.path(), kind).unwrap();
+
+            backend
+                .checkout_entry(&StackEntry::new("branch-2"), false)
+                .unwrap();
+            assert_that(&head_branch(&repo))
+                .is_some()
+                .is_equal_to("branch-2".to_string());
+
+            let oid = repo.head().unwrap().target().unwrap();
+            backend
+                .checkout_entry(&StackEntry::commit(oid.to_string()), false)
+                .unwrap();
+            assert_that(&head_branch(&repo)).is_none();
+            assert_that(&backend.current_branch_name())
+                .is_err()
+                .matches(|v| matches!(v, BranchStackError::NoCurrrentBranch));
+        }
+    }
+
+    #[test]
+    fn test_backends_read_head() {
+        for kind in all_kinds() {
+            let (working_dir, repo) = setup_repo();
+            let backend = open_backend(working_dir.path(), kind).unwrap();
+            let oid = repo.head().unwrap().target().unwrap();
+
+            assert_that(&backend.current_branch_name().unwrap()).is_equal_to(b"master".to_vec());
+            assert_that(&backend.head_commit().unwrap()).is_equal_to(oid.to_string());
+
+            backend.change_branch("branch-2").unwrap();
+            assert_that(&backend.current_branch_name().unwrap()).is_equal_to(b"branch-2".to_vec());
+
+            repo.set_head_detached(oid).unwrap();
+            assert_that(&backend.current_branch_name())
+                .is_err()
+                .matches(|v| matches!(v, BranchStackError::NoCurrrentBranch));
+            assert_that(&backend.head_commit().unwrap()).is_equal_to(oid.to_string());
+
+            repo.set_head("refs/heads/unborn").unwrap();
+            assert_that(&backend.head_commit())
+                .is_err()
+                .matches(|v| matches!(v, BranchStackError::UnbornHead));
+        }
+    }
+
+    #[test]
+    fn test_backends_read_refs_and_revisions() {
+        for kind in all_kinds() {
+            let (working_dir, repo) = setup_repo();
+            let backend = open_backend(working_dir.path(), kind).unwrap();
+            let oid = repo.head().unwrap().target().unwrap().to_string();
+
+            assert_that(&backend.find_ref(b"refs/heads/branch-2").unwrap())
+                .is_some()
+                .is_equal_to(oid.clone());
+            assert_that(&backend.find_ref(b"refs/heads/nope").unwrap()).is_none();
+            assert_that(&backend.refs("refs/heads/").unwrap()).is_equal_to(vec![
+                (b"refs/heads/branch-2".to_vec(), oid.clone()),
+                (b"refs/heads/master".to_vec(), oid.clone()),
+            ]);
+
+            assert_that(&backend.resolve_revision("branch-2").unwrap())
+                .is_equal_to((oid.clone(), Some(b"refs/heads/branch-2".to_vec())));
+            assert_that(&backend.resolve_revision(&oid[..7]).unwrap())
+                .is_equal_to((oid.clone(), None));
+            assert_that(&backend.resolve_revision("master~1"))
+                .is_err()
+                .matches(|v| matches!(v, BranchStackError::InvalidRevision(_)));
+            assert_that(&backend.is_ancestor(&oid, &oid).unwrap()).is_true();
+
+            backend.create_branch("feature", &oid).unwrap();
+            assert_that(&backend.create_branch("feature", &oid))
+                .is_err()
+                .matches(|v| matches!(v, BranchStackError::BranchExists(_)));
+            backend
+                .set_config("branch.feature.remote", "origin")
+                .unwrap();
+            backend.delete_branch("feature").unwrap();
+            assert_that(&backend.find_ref(b"refs/heads/feature").unwrap()).is_none();
+            assert_that(&repo.config().unwrap().get_string("branch.feature.remote")).is_err();
+        }
+    }
+
+    #[test]
+    fn test_backends_read_and_write_config() {
+        for kind in all_kinds() {
+            let (working_dir, repo) = setup_repo();
+            let backend = open_backend(working_dir.path(), kind).unwrap();
+
+            assert_that(&backend.config_string("branchstack.stack").unwrap()).is_none();
+            backend.set_config("branchstack.stack", "review").unwrap();
+            assert_that(&backend.config_string("branchstack.stack").unwrap())
+                .is_some()
+                .is_equal_to("review".to_string());
+            assert_that(
+                &repo
+                    .config()
+                    .unwrap()
+                    .get_string("branchstack.stack")
+                    .unwrap(),
+            )
+            .is_equal_to("review".to_string());
+
+            backend.set_config("submodule.recurse", "true").unwrap();
+            assert_that(&backend.config_bool("submodule.recurse").unwrap())
+                .is_some()
+                .is_true();
+        }
+    }
+
+    #[test]
+    fn test_backends_stash_changes() {
+        for kind in all_kinds() {
+            let (working_dir, repo) = setup_repo();
+            let backend = open_backend(working_dir.path(), kind).unwrap();
+            let file = working_dir.path().join("file-1");
+            assert_that(&backend.is_dirty().unwrap()).is_false();
+
+            write(&file, "changed\n").unwrap();
+            assert_that(&backend.is_dirty().unwrap()).is_true();
+            let stash = backend.stash_changes("saved").unwrap();
+            assert_that(&backend.is_dirty().unwrap()).is_false();
+            assert_that(&std::fs::read_to_string(&file).unwrap())
+                .is_equal_to("first\n".to_string());
+            assert_that(&repo.refname_to_id("refs/stash").unwrap().to_string())
+                .is_equal_to(stash.clone());
+
+            backend.pop_stash(&stash).unwrap();
+            assert_that(&std::fs::read_to_string(&file).unwrap())
+                .is_equal_to("changed\n".to_string());
+            assert_that(&repo.find_reference("refs/stash").is_err()).is_true();
+
+            // a stash that's already gone is left alone
+            backend.pop_stash(&stash).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_backends_check_out_non_utf8_branches() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        for kind in all_kinds() {
+            let (working_dir, repo) = setup_repo();
+            let oid = repo.head().unwrap().target().unwrap();
+            write(
+                repo.path().join(OsStr::from_bytes(b"refs/heads/caf\xe9")),
+                format!("{}\n", oid),
+            )
+            .unwrap();
+            let backend = open_backend(working_dir.path(), kind).unwrap();
+
+            backend
+                .checkout_entry(&StackEntry::from_bytes(b"caf\xe9"), false)
+                .unwrap();
+            let head = repo.find_reference("HEAD").unwrap();
+            assert_that(&head.symbolic_target_bytes())
+                .is_some()
+                .is_equal_to(&b"refs/heads/caf\xe9"[..]);
+            assert_that(&backend.current_branch_name().unwrap()).is_equal_to(b"caf\xe9".to_vec());
+            assert_that(&backend.find_ref(b"refs/heads/caf\xe9").unwrap())
+                .is_some()
+                .is_equal_to(oid.to_string());
+        }
+    }
+}
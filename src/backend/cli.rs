@@ -0,0 +1,328 @@
+/// # git Backend
+///
+/// This backend runs the installed `git` binary to read HEAD and check
+/// entries out, so `git checkout` does the work, hooks and all. Everything
+/// else is handed to the default backend, which reads the same repository
+/// in-process.
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{open_backend, BackendKind, GitBackend};
+use crate::errors::{BranchStackError, Result};
+use crate::git::{CheckoutConflict, ConflictReason};
+use crate::stack::StackEntry;
+
+/// The backend that runs the `git` binary in the working tree.
+pub struct CliBackend {
+    dir: PathBuf,
+    repo: Box<dyn GitBackend>,
+}
+
+impl CliBackend {
+    /// Open the repository at `path`, or the one that `path` is in. `git`
+    /// is run in its working tree, or in its git directory if it's bare.
+    pub fn open(path: &Path) -> Result<CliBackend> {
+        let repo = open_backend(path, BackendKind::default())?;
+        let dir = repo.workdir().unwrap_or_else(|| repo.git_dir());
+        Ok(CliBackend {
+            dir: dir.to_path_buf(),
+            repo,
+        })
+    }
+
+    /// Run `git` with `args` and return what it printed, trimmed. If it
+    /// fails, this returns `Err(BranchStackError::GitCommandFailed)` with
+    /// what it printed to stderr.
+    fn git<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<String> {
+        let stdout = self.git_bytes(args)?;
+        Ok(String::from_utf8_lossy(&stdout).trim().to_string())
+    }
+
+    /// Run `git` with `args` like `git` does, but return exactly the bytes
+    /// it printed, for output like ref names that may not be valid UTF-8.
+    fn git_bytes<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Vec<u8>> {
+        // git's messages are read back in `checkout`, so they have to be
+        // the untranslated ones.
+        let output = Command::new("git")
+            .args(args)
+            .env("LC_ALL", "C")
+            .current_dir(&self.dir)
+            .output()?;
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            let command = args
+                .iter()
+                .map(|arg| arg.as_ref().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Err(BranchStackError::GitCommandFailed(
+                format!("git {}", command),
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ))
+        }
+    }
+
+    /// Run `git checkout` on `target`, given as the bytes of a ref name or a
+    /// commit id. Other worktrees have already been checked by the time
+    /// anything is checked out, so git isn't asked to check them again. If
+    /// git refuses because local changes are in the way, this returns
+    /// `Err(BranchStackError::CheckoutConflicts)` listing them, the same as
+    /// the other backends.
+    fn checkout(&self, target: &[u8], detach: bool, force: bool) -> Result<()> {
+        let mut args = vec![
+            OsString::from("checkout"),
+            OsString::from("--quiet"),
+            OsString::from("--ignore-other-worktrees"),
+        ];
+        if detach {
+            args.push(OsString::from("--detach"));
+        }
+        if force {
+            args.push(OsString::from("--force"));
+        }
+        args.push(os_string_from_bytes(target));
+        args.push(OsString::from("--"));
+        match self.git(&args) {
+            Err(BranchStackError::GitCommandFailed(command, stderr)) => {
+                let conflicts = parse_checkout_conflicts(&stderr);
+                if conflicts.is_empty() {
+                    Err(BranchStackError::GitCommandFailed(command, stderr))
+                } else {
+                    Err(BranchStackError::CheckoutConflicts(conflicts))
+                }
+            }
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+/// Read the paths that `git checkout` said were in the way out of what it
+/// printed to stderr. Each list starts with a line saying what would happen
+/// to the files, and the paths follow it, indented with a tab.
+fn parse_checkout_conflicts(stderr: &str) -> Vec<CheckoutConflict> {
+    let mut conflicts = Vec::new();
+    let mut reason = None;
+    for line in stderr.lines() {
+        if let Some(path) = line.strip_prefix('\t') {
+            if let Some(reason) = reason {
+                conflicts.push(CheckoutConflict {
+                    path: PathBuf::from(path),
+                    reason,
+                });
+            }
+        } else if line.contains("local changes to the following files would be") {
+            reason = Some(ConflictReason::Modified);
+        } else if line.contains("untracked working tree files would be") {
+            reason = Some(ConflictReason::Untracked);
+        } else {
+            reason = None;
+        }
+    }
+    conflicts
+}
+
+/// Turn the bytes of a ref name into an argument for `git`. Outside of
+/// Unix, arguments have to be Unicode, so a name that isn't valid UTF-8 is
+/// converted lossily.
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+
+    OsStr::from_bytes(bytes).to_os_string()
+}
+
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Is this the error from a `git` command run with `--quiet` that failed
+/// without saying anything? Commands like `git symbolic-ref --quiet` do
+/// that when the answer is just "no".
+fn is_quiet_failure(err: &BranchStackError) -> bool {
+    matches!(err, BranchStackError::GitCommandFailed(_, stderr) if stderr.is_empty())
+}
+
+impl GitBackend for CliBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Cli
+    }
+
+    fn git_dir(&self) -> &Path {
+        self.repo.git_dir()
+    }
+
+    fn workdir(&self) -> Option<&Path> {
+        self.repo.workdir()
+    }
+
+    fn current_branch_name(&self) -> Result<Vec<u8>> {
+        let refname = match self.git_bytes(&["symbolic-ref", "--quiet", "HEAD"]) {
+            Err(ref err) if is_quiet_failure(err) => {
+                return Err(BranchStackError::NoCurrrentBranch)
+            }
+            refname => refname?,
+        };
+        refname
+            .trim_ascii_end()
+            .strip_prefix(b"refs/heads/")
+            .map(<[u8]>::to_vec)
+            .ok_or(BranchStackError::NoCurrrentBranch)
+    }
+
+    fn head_commit(&self) -> Result<String> {
+        match self.git(&["rev-parse", "--verify", "--quiet", "HEAD^{commit}"]) {
+            Err(ref err) if is_quiet_failure(err) => Err(BranchStackError::UnbornHead),
+            commit => commit,
+        }
+    }
+
+    fn change_branch(&self, branch_name: &str) -> Result<()> {
+        self.checkout(branch_name.as_bytes(), false, false)
+    }
+
+    fn checkout_entry(&self, entry: &StackEntry, force: bool) -> Result<()> {
+        if entry.is_branch() {
+            self.checkout(entry.name_bytes(), false, force)
+        } else {
+            let target = entry
+                .refname_bytes()
+                .unwrap_or_else(|| entry.name_bytes().to_vec());
+            self.checkout(&target, true, force)
+        }
+    }
+
+    fn runs_hooks(&self) -> bool {
+        true
+    }
+
+    fn find_ref(&self, refname: &[u8]) -> Result<Option<String>> {
+        self.repo.find_ref(refname)
+    }
+
+    fn refs(&self, prefix: &str) -> Result<Vec<(Vec<u8>, String)>> {
+        self.repo.refs(prefix)
+    }
+
+    fn resolve_revision(&self, revision: &str) -> Result<(String, Option<Vec<u8>>)> {
+        self.repo.resolve_revision(revision)
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        self.repo.is_ancestor(ancestor, descendant)
+    }
+
+    fn remotes(&self) -> Result<Vec<String>> {
+        self.repo.remotes()
+    }
+
+    fn create_branch(&self, name: &str, commit: &str) -> Result<()> {
+        self.repo.create_branch(name, commit)
+    }
+
+    fn delete_branch(&self, name: &str) -> Result<()> {
+        self.repo.delete_branch(name)
+    }
+
+    fn delete_ref(&self, refname: &str) -> Result<()> {
+        self.repo.delete_ref(refname)
+    }
+
+    fn config_string(&self, key: &str) -> Result<Option<String>> {
+        self.repo.config_string(key)
+    }
+
+    fn config_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.repo.config_bool(key)
+    }
+
+    fn set_config(&self, key: &str, value: &str) -> Result<()> {
+        self.repo.set_config(key, value)
+    }
+
+    fn read_ref_file(&self, refname: &str, filename: &str) -> Result<Option<Vec<u8>>> {
+        self.repo.read_ref_file(refname, filename)
+    }
+
+    fn commit_ref_file(
+        &self,
+        refname: &str,
+        filename: &str,
+        contents: &[u8],
+        message: &str,
+    ) -> Result<String> {
+        self.repo
+            .commit_ref_file(refname, filename, contents, message)
+    }
+
+    fn is_dirty(&self) -> Result<bool> {
+        self.repo.is_dirty()
+    }
+
+    fn stash_changes(&self, message: &str) -> Result<String> {
+        self.repo.stash_changes(message)
+    }
+
+    fn pop_stash(&self, stash: &str) -> Result<()> {
+        self.repo.pop_stash(stash)
+    }
+
+    fn submodules(&self) -> Result<Vec<(PathBuf, String)>> {
+        self.repo.submodules()
+    }
+
+    fn fetch(&self) -> Result<()> {
+        self.git(&["fetch", "--quiet"]).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Repository;
+    use spectral::prelude::*;
+    use tempfile::tempdir;
+
+    use super::{parse_checkout_conflicts, CliBackend};
+    use crate::backend::GitBackend;
+    use crate::errors::BranchStackError;
+    use crate::git::{CheckoutConflict, ConflictReason};
+    use crate::stack::StackEntry;
+
+    #[test]
+    fn test_parse_checkout_conflicts() {
+        let stderr = "error: Your local changes to the following files would be \
+                      overwritten by checkout:\n\
+                      \tfile-1\n\
+                      Please commit your changes or stash them before you switch branches.\n\
+                      error: The following untracked working tree files would be \
+                      overwritten by checkout:\n\
+                      \tdir/file-2\n\
+                      Please move or remove them before you switch branches.\n\
+                      Aborting";
+        assert_that(&parse_checkout_conflicts(stderr)).is_equal_to(vec![
+            CheckoutConflict {
+                path: "file-1".into(),
+                reason: ConflictReason::Modified,
+            },
+            CheckoutConflict {
+                path: "dir/file-2".into(),
+                reason: ConflictReason::Untracked,
+            },
+        ]);
+        assert_that(&parse_checkout_conflicts("fatal: invalid reference: nope")).is_empty();
+    }
+
+    #[test]
+    fn test_cli_backend_reports_git_errors() {
+        let working_dir = tempdir().unwrap();
+        Repository::init(working_dir.path()).unwrap();
+        let backend = CliBackend::open(working_dir.path()).unwrap();
+
+        let result = backend.checkout_entry(&StackEntry::new("no-such-branch"), false);
+        assert_that(&result)
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::GitCommandFailed(_, _)));
+    }
+}
@@ -0,0 +1,1114 @@
+/// # gitoxide Backend
+///
+/// This backend uses gitoxide (`gix`), so nothing goes through libgit2.
+/// It's only built with the `gix` cargo feature.
+///
+/// gitoxide doesn't have a high-level checkout for moving between
+/// branches, so this works one out from the two indexes: files that are
+/// the same on both sides are left alone, files that are only on the old
+/// side are removed, and everything else is written from the new tree.
+/// Uncommitted changes are carried along like libgit2 does, as long as
+/// the file is the same on both branches; otherwise they're reported as
+/// conflicts, unless the checkout is forced, which overwrites everything.
+///
+/// Stashes are made and applied here too, since gitoxide doesn't have
+/// them either. Applying one only merges whole files, so a file that was
+/// changed both in the stash and since it was made is a conflict. Fetching
+/// isn't supported; run `git fetch` instead.
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{read, remove_dir, remove_file};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use gix::bstr::{BStr, BString, ByteSlice, ByteVec};
+use gix::index::entry::{Flags, Mode};
+use gix::objs::tree::{EntryKind, EntryMode};
+use gix::refs::transaction::{LogChange, PreviousValue, RefEdit, RefLog};
+use gix::refs::{FullName, Target};
+use gix::status::UntrackedFiles;
+use gix::worktree::stack::state::attributes::Source;
+use gix::ObjectId;
+
+use super::{BackendKind, GitBackend};
+use crate::errors::{BranchStackError, Result};
+use crate::git::{CheckoutConflict, ConflictReason};
+use crate::lock::write_atomic;
+use crate::stack::StackEntry;
+
+/// The ref that stashes are kept in.
+const STASH_REF: &str = "refs/stash";
+
+fn backend_error<E: fmt::Display>(err: E) -> BranchStackError {
+    BranchStackError::BackendError(err.to_string())
+}
+
+/// The backend that uses gitoxide.
+pub struct GixBackend {
+    repo: gix::Repository,
+    /// The config as it was read when the repository was opened, with the
+    /// changes made through this backend since.
+    config: RefCell<gix::config::File>,
+}
+
+impl GixBackend {
+    /// Open the repository at `path`, or the one that `path` is in.
+    pub fn open(path: &Path) -> Result<GixBackend> {
+        let mut repo = gix::discover(path).map_err(backend_error)?;
+        // Commits and reflog entries need a committer, so this falls back
+        // to the same one as the libgit2 backend.
+        repo.committer_or_set_fallback("git-branch-stack", "git-branch-stack@localhost")
+            .map_err(backend_error)?;
+        let config = RefCell::new(repo.config_snapshot().plumbing().clone());
+        Ok(GixBackend { repo, config })
+    }
+
+    /// The working tree. If the repository is bare, this returns
+    /// `Err(BranchStackError::BareRepository)`.
+    fn require_workdir(&self) -> Result<&Path> {
+        self.repo.workdir().ok_or(BranchStackError::BareRepository)
+    }
+
+    /// The committer from the git config, or the stand-in set when the
+    /// repository was opened.
+    fn signature(&self) -> Result<gix::actor::SignatureRef<'_>> {
+        self.repo
+            .committer()
+            .ok_or_else(|| BranchStackError::BackendError("no committer is set".to_string()))?
+            .map_err(backend_error)
+    }
+
+    /// Look up a reference by the bytes of its full name, returning `None`
+    /// if it doesn't exist or its name isn't valid.
+    fn find_reference(&self, refname: &[u8]) -> Result<Option<gix::Reference<'_>>> {
+        let name = match <&gix::refs::PartialNameRef>::try_from(refname.as_bstr()) {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        };
+        self.repo.try_find_reference(name).map_err(backend_error)
+    }
+
+    /// Make a change to the repository's own config file, and to the copy
+    /// of the config this backend reads from.
+    fn edit_config<F>(&self, edit: F) -> Result<()>
+    where
+        F: Fn(&mut gix::config::File) -> Result<()>,
+    {
+        let path = self.repo.common_dir().join("config");
+        let mut file =
+            gix::config::File::from_path_no_includes(path.clone(), gix::config::Source::Local)
+                .map_err(backend_error)?;
+        edit(&mut file)?;
+        let mut contents = Vec::new();
+        file.write_to(&mut contents)?;
+        write_atomic(&path, &contents)?;
+        edit(&mut self.config.borrow_mut())
+    }
+
+    /// Write a tree holding `entries`, which are by path.
+    fn write_tree(&self, entries: &HashMap<BString, (ObjectId, Mode)>) -> Result<ObjectId> {
+        let mut editor = self
+            .repo
+            .edit_tree(ObjectId::empty_tree(self.repo.object_hash()))
+            .map_err(backend_error)?;
+        for (path, (id, mode)) in entries {
+            let kind = mode
+                .to_tree_entry_mode()
+                .ok_or_else(|| BranchStackError::BackendError(format!("bad mode for {}", path)))?
+                .kind();
+            editor
+                .upsert(path.as_bstr(), kind, *id)
+                .map_err(backend_error)?;
+        }
+        Ok(editor.write().map_err(backend_error)?.detach())
+    }
+
+    /// The paths with uncommitted changes, whether they're staged or not.
+    /// Untracked files aren't included.
+    fn changed_paths(&self) -> Result<HashSet<BString>> {
+        let items = self
+            .repo
+            .status(gix::progress::Discard)
+            .map_err(backend_error)?
+            .untracked_files(UntrackedFiles::None)
+            .index_worktree_rewrites(None)
+            .index_worktree_submodules(None)
+            .into_iter(None)
+            .map_err(backend_error)?;
+        let mut paths = HashSet::new();
+        for item in items {
+            paths.insert(item.map_err(backend_error)?.location().to_owned());
+        }
+        Ok(paths)
+    }
+
+    /// The entries of the tree `id`, by path.
+    fn tree_entries(&self, id: ObjectId) -> Result<HashMap<BString, (ObjectId, Mode)>> {
+        let index = self.repo.index_from_tree(&id).map_err(backend_error)?;
+        Ok(index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path(&index).to_owned(), (entry.id, entry.mode)))
+            .collect())
+    }
+
+    /// Update the working tree and the index to the tree of `commit`.
+    /// Uncommitted changes to files that are the same on both sides are
+    /// kept. Unless `force` is set, this returns
+    /// `Err(BranchStackError::CheckoutConflicts)` if any other changes, or
+    /// untracked files, would be overwritten.
+    fn checkout_tree(&self, commit: ObjectId, force: bool) -> Result<()> {
+        let workdir = self.require_workdir()?;
+        let tree = self
+            .repo
+            .find_commit(commit)
+            .map_err(backend_error)?
+            .tree_id()
+            .map_err(backend_error)?;
+        let old_index = self.repo.index_or_empty().map_err(backend_error)?;
+        let mut new_index = self.repo.index_from_tree(&tree).map_err(backend_error)?;
+
+        let mut old_entries: HashMap<BString, _> = old_index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path(&old_index).to_owned(), entry.clone()))
+            .collect();
+        // Forcing throws the changes away, so there's nothing to look for.
+        let (changed, head_entries) = if force {
+            (HashSet::new(), HashMap::new())
+        } else {
+            let head_tree = self.repo.head_tree_id().map_err(backend_error)?;
+            (
+                self.changed_paths()?,
+                self.tree_entries(head_tree.detach())?,
+            )
+        };
+        let mut conflicts = Vec::new();
+        let mut unstaged = HashSet::new();
+        let mut replaced_dirs = Vec::new();
+        for (entry, path) in new_index.entries_mut_with_paths() {
+            let old = old_entries.remove(path);
+            if changed.contains(path) {
+                if head_entries.get(path) != Some(&(entry.id, entry.mode)) {
+                    conflicts.push(CheckoutConflict {
+                        path: path.to_path_lossy().into_owned(),
+                        reason: ConflictReason::Modified,
+                    });
+                    continue;
+                }
+                // It's the same on both branches, so the changes are
+                // carried along, including whatever's staged.
+                match old {
+                    Some(old) => {
+                        entry.id = old.id;
+                        entry.mode = old.mode;
+                        entry.stat = old.stat;
+                        entry.flags.insert(Flags::SKIP_WORKTREE);
+                    }
+                    None => {
+                        unstaged.insert(path.to_owned());
+                    }
+                }
+                continue;
+            }
+            match old {
+                // It's already there, so it's skipped, and its stat info
+                // is kept so git doesn't have to look at it again.
+                Some(old) if !force && old.id == entry.id && old.mode == entry.mode => {
+                    entry.stat = old.stat;
+                    entry.flags.insert(Flags::SKIP_WORKTREE);
+                }
+                Some(_) => {}
+                None => {
+                    let exists = workdir
+                        .join(path.to_path_lossy())
+                        .symlink_metadata()
+                        .is_ok();
+                    // A directory that only held tracked files is cleared
+                    // out before the checkout, so it's not in the way.
+                    let mut dir = path.to_owned();
+                    dir.push(b'/');
+                    if old_entries.keys().any(|old| old.starts_with(&dir)) {
+                        replaced_dirs.push(dir);
+                        continue;
+                    }
+                    if exists && !force {
+                        conflicts.push(CheckoutConflict {
+                            path: path.to_path_lossy().into_owned(),
+                            reason: ConflictReason::Untracked,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Whatever's left isn't on the new branch. Files that were added
+        // since the last commit are carried along, but files with changes
+        // that would be deleted are conflicts.
+        let mut added = Vec::new();
+        for (path, entry) in &old_entries {
+            if !changed.contains(path) {
+                continue;
+            }
+            if head_entries.contains_key(path) {
+                conflicts.push(CheckoutConflict {
+                    path: path.to_path_lossy().into_owned(),
+                    reason: ConflictReason::Deleted,
+                });
+            } else {
+                added.push((path, entry));
+            }
+        }
+        if !conflicts.is_empty() {
+            conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+            return Err(BranchStackError::CheckoutConflicts(conflicts));
+        }
+
+        new_index.remove_entries(|_, path, _| unstaged.contains(path));
+        for (path, entry) in added {
+            new_index.dangerously_push_entry(
+                entry.stat,
+                entry.id,
+                entry.flags | Flags::SKIP_WORKTREE,
+                entry.mode,
+                path.as_ref(),
+            );
+        }
+        new_index.sort_entries();
+
+        let (in_the_way, removed): (Vec<_>, Vec<_>) = old_entries
+            .keys()
+            .filter(|path| !changed.contains(*path))
+            .partition(|path| replaced_dirs.iter().any(|dir| path.starts_with(dir)));
+        for path in in_the_way {
+            remove_from_workdir(workdir, &path.to_path_lossy());
+        }
+
+        self.write_files(&mut new_index, workdir)?;
+
+        // Everything else is only removed once the new files are written,
+        // so a failed checkout doesn't leave the working tree half switched.
+        for path in removed {
+            remove_from_workdir(workdir, &path.to_path_lossy());
+        }
+
+        for entry in new_index.entries_mut() {
+            entry.flags.remove(Flags::SKIP_WORKTREE);
+        }
+        new_index.write(Default::default()).map_err(backend_error)?;
+        Ok(())
+    }
+
+    /// Write the files in `index` to the working tree at `workdir`,
+    /// overwriting whatever's there, except for entries marked to be
+    /// skipped.
+    fn write_files(&self, index: &mut gix::index::State, workdir: &Path) -> Result<()> {
+        let mut options = self
+            .repo
+            .checkout_options(Source::IdMapping)
+            .map_err(backend_error)?;
+        options.overwrite_existing = true;
+        let objects = self
+            .repo
+            .objects
+            .clone()
+            .into_arc()
+            .map_err(backend_error)?;
+        let outcome = gix::worktree::state::checkout(
+            index,
+            workdir,
+            objects,
+            &gix::progress::Discard,
+            &gix::progress::Discard,
+            &AtomicBool::new(false),
+            options,
+        )
+        .map_err(backend_error)?;
+        if let Some(error) = outcome.errors.first() {
+            return Err(BranchStackError::BackendError(format!(
+                "couldn't check out {}: {}",
+                error.path, error.error
+            )));
+        }
+        Ok(())
+    }
+
+    /// Move HEAD to `target`, which is at the commit `id`, and write the
+    /// reflog entry for it. `to` is what it's called in the entry.
+    fn set_head(&self, target: Target, id: ObjectId, to: &[u8]) -> Result<()> {
+        let from = match self.repo.head_name().map_err(backend_error)? {
+            Some(name) => name.shorten().to_owned(),
+            None => self
+                .repo
+                .head_id()
+                .map_err(backend_error)?
+                .to_string()
+                .into(),
+        };
+        let mut message = BString::from("checkout: moving from ");
+        message.push_str(from);
+        message.push_str(" to ");
+        message.push_str(to);
+        let head = FullName::try_from("HEAD").map_err(backend_error)?;
+
+        let log = match target {
+            // gitoxide writes the target of a symbolic ref as text, which
+            // mangles names that aren't valid UTF-8, so HEAD is written
+            // directly for those.
+            Target::Symbolic(ref name) if name.as_bstr().to_str().is_err() => {
+                let contents = [b"ref: ".as_ref(), name.as_bstr(), b"\n"].concat();
+                write_atomic(self.repo.git_dir().join("HEAD"), &contents)?;
+                RefLog::Only
+            }
+            // gitoxide doesn't write reflog entries for changes to
+            // symbolic refs, so HEAD is moved first, and the entry is
+            // written by itself afterward.
+            Target::Symbolic(_) => {
+                self.repo
+                    .edit_reference(RefEdit::update(
+                        head.clone(),
+                        target,
+                        PreviousValue::Any,
+                        "",
+                    ))
+                    .map_err(backend_error)?;
+                RefLog::Only
+            }
+            Target::Object(_) => RefLog::AndReference,
+        };
+        self.repo
+            .edit_reference(RefEdit::update_with_log(
+                head,
+                Target::Object(id),
+                PreviousValue::Any,
+                LogChange {
+                    mode: log,
+                    force_create_reflog: false,
+                    message,
+                },
+            ))
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    /// The commit that `revision` points to.
+    fn find_commit(&self, revision: &BStr) -> Result<ObjectId> {
+        let id = self
+            .repo
+            .rev_parse_single(revision)
+            .map_err(|_| BranchStackError::InvalidRevision(revision.to_str_lossy().into_owned()))?
+            .object()
+            .map_err(backend_error)?
+            .peel_to_commit()
+            .map_err(backend_error)?
+            .id;
+        Ok(id)
+    }
+
+    /// Apply the changes in the stash commit `stash` to the working tree,
+    /// a whole file at a time. Files the stash adds are added to the index
+    /// too. If any file was changed both in the stash and since it was
+    /// made, or has local changes, nothing is changed, and this returns an
+    /// error listing them.
+    fn apply_stash(&self, stash: ObjectId) -> Result<()> {
+        let workdir = self.require_workdir()?;
+        let stash = self.repo.find_commit(stash).map_err(backend_error)?;
+        let base = match stash.parent_ids().next() {
+            Some(base) => base.detach(),
+            None => {
+                return Err(BranchStackError::BackendError(format!(
+                    "{} isn't a stash",
+                    stash.id
+                )))
+            }
+        };
+        let base_tree = self
+            .repo
+            .find_commit(base)
+            .map_err(backend_error)?
+            .tree_id()
+            .map_err(backend_error)?;
+        let base = self.tree_entries(base_tree.detach())?;
+        let theirs = self.tree_entries(stash.tree_id().map_err(backend_error)?.detach())?;
+        let ours = self.tree_entries(self.repo.head_tree_id().map_err(backend_error)?.detach())?;
+        let changed = self.changed_paths()?;
+
+        let paths: HashSet<&BString> = base.keys().chain(theirs.keys()).collect();
+        let mut updates = Vec::new();
+        let mut conflicts = Vec::new();
+        for path in paths {
+            let (base, theirs, ours) = (base.get(path), theirs.get(path), ours.get(path));
+            let is_submodule = |entry: Option<&(ObjectId, Mode)>| {
+                entry.is_some_and(|(_, mode)| mode.is_submodule())
+            };
+            if theirs == base || ours == theirs || is_submodule(theirs) || is_submodule(base) {
+                continue;
+            }
+            let in_the_way = changed.contains(path)
+                || (ours.is_none()
+                    && workdir
+                        .join(path.to_path_lossy())
+                        .symlink_metadata()
+                        .is_ok());
+            if ours != base || in_the_way {
+                conflicts.push(path.to_str_lossy().into_owned());
+            } else {
+                updates.push((path, theirs, ours.is_none()));
+            }
+        }
+        if !conflicts.is_empty() {
+            conflicts.sort();
+            return Err(BranchStackError::BackendError(format!(
+                "the stash has changes to files that changed since: {}",
+                conflicts.join(", ")
+            )));
+        }
+
+        let mut files = gix::index::State::new(self.repo.object_hash());
+        let mut removed = Vec::new();
+        let mut added = HashSet::new();
+        for (path, entry, is_new) in updates {
+            match entry {
+                Some((id, mode)) => {
+                    files.dangerously_push_entry(
+                        Default::default(),
+                        *id,
+                        Flags::empty(),
+                        *mode,
+                        path.as_bstr(),
+                    );
+                    if is_new {
+                        added.insert(path);
+                    }
+                }
+                None => removed.push(path),
+            }
+        }
+        files.sort_entries();
+        self.write_files(&mut files, workdir)?;
+        for path in removed {
+            remove_from_workdir(workdir, &path.to_path_lossy());
+        }
+
+        if !added.is_empty() {
+            let mut index = self.repo.open_index().map_err(backend_error)?;
+            for entry in files.entries() {
+                let path = entry.path(&files);
+                if added.contains(&path.to_owned()) {
+                    index.dangerously_push_entry(
+                        entry.stat,
+                        entry.id,
+                        Flags::empty(),
+                        entry.mode,
+                        path,
+                    );
+                }
+            }
+            index.sort_entries();
+            index.write(Default::default()).map_err(backend_error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Is `revision` like `@{-1}`, naming a branch that was checked out
+/// before?
+fn is_previous_branch(revision: &str) -> bool {
+    revision
+        .strip_prefix("@{-")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Remove `path` from the working tree, along with any directories that
+/// it leaves empty.
+fn remove_from_workdir(workdir: &Path, path: &Path) {
+    let file = workdir.join(path);
+    if remove_file(&file).is_err() {
+        return;
+    }
+    let mut dir = file.parent();
+    while let Some(parent) = dir {
+        if parent == workdir || remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+impl GitBackend for GixBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Gix
+    }
+
+    fn git_dir(&self) -> &Path {
+        self.repo.git_dir()
+    }
+
+    fn workdir(&self) -> Option<&Path> {
+        self.repo.workdir()
+    }
+
+    fn current_branch_name(&self) -> Result<Vec<u8>> {
+        let refname = self.repo.head_name().map_err(backend_error)?;
+        refname
+            .as_ref()
+            .and_then(|refname| refname.as_bstr().strip_prefix(b"refs/heads/"))
+            .map(<[u8]>::to_vec)
+            .ok_or(BranchStackError::NoCurrrentBranch)
+    }
+
+    fn head_commit(&self) -> Result<String> {
+        let head = self.repo.head().map_err(backend_error)?;
+        if head.is_unborn() {
+            return Err(BranchStackError::UnbornHead);
+        }
+        let id = self.repo.head_commit().map_err(backend_error)?.id;
+        Ok(id.to_string())
+    }
+
+    fn change_branch(&self, branch_name: &str) -> Result<()> {
+        self.checkout_entry(&StackEntry::new(branch_name), false)
+    }
+
+    fn checkout_entry(&self, entry: &StackEntry, force: bool) -> Result<()> {
+        let refname = entry.refname_bytes();
+        let revision = refname
+            .clone()
+            .unwrap_or_else(|| entry.name_bytes().to_vec());
+        let id = self.find_commit(revision.as_bstr())?;
+        self.checkout_tree(id, force)?;
+
+        let target = match refname {
+            Some(refname) if entry.is_branch() => {
+                Target::Symbolic(FullName::try_from(BString::from(refname)).map_err(backend_error)?)
+            }
+            _ => Target::Object(id),
+        };
+        self.set_head(target, id, entry.reflog_name())
+    }
+
+    fn find_ref(&self, refname: &[u8]) -> Result<Option<String>> {
+        match self.find_reference(refname)? {
+            Some(mut reference) => {
+                let commit = reference.peel_to_commit().map_err(backend_error)?;
+                Ok(Some(commit.id.to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn refs(&self, prefix: &str) -> Result<Vec<(Vec<u8>, String)>> {
+        let mut refs = Vec::new();
+        let references = self.repo.references().map_err(backend_error)?;
+        for reference in references.all().map_err(backend_error)? {
+            let mut reference = reference.map_err(backend_error)?;
+            let name = reference.name().as_bstr().to_vec();
+            if !name.starts_with(prefix.as_bytes()) {
+                continue;
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                refs.push((name, commit.id.to_string()));
+            }
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    fn resolve_revision(&self, revision: &str) -> Result<(String, Option<Vec<u8>>)> {
+        let invalid = || BranchStackError::InvalidRevision(revision.to_string());
+        let spec = self.repo.rev_parse(revision).map_err(|_| invalid())?;
+        let id = spec.single().ok_or_else(invalid)?;
+        let commit = id
+            .object()
+            .map_err(backend_error)?
+            .peel_to_commit()
+            .map_err(backend_error)?;
+        // Only a revision that names a ref comes with the ref's name, so
+        // `master` does and `master~1` doesn't. `@{-1}` names the branch
+        // that was checked out before it.
+        let refname = if is_previous_branch(revision) {
+            spec.first_reference()
+                .map(|reference| reference.name.as_bstr().to_vec())
+        } else {
+            self.find_reference(revision.as_bytes())?
+                .map(|reference| reference.name().as_bstr().to_vec())
+        };
+        Ok((commit.id.to_string(), refname))
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        let ancestor = ObjectId::from_hex(ancestor.as_bytes()).map_err(backend_error)?;
+        let descendant = ObjectId::from_hex(descendant.as_bytes()).map_err(backend_error)?;
+        if ancestor == descendant {
+            return Ok(true);
+        }
+        let base = self
+            .repo
+            .merge_base(ancestor, descendant)
+            .map_err(backend_error)?;
+        Ok(base.map(|base| base.detach()) == Some(ancestor))
+    }
+
+    fn remotes(&self) -> Result<Vec<String>> {
+        let remotes = self.repo.remote_names();
+        Ok(remotes
+            .iter()
+            .map(|name| name.to_str_lossy().into_owned())
+            .collect())
+    }
+
+    fn create_branch(&self, name: &str, commit: &str) -> Result<()> {
+        let refname = format!("refs/heads/{}", name);
+        if self.find_reference(refname.as_bytes())?.is_some() {
+            return Err(BranchStackError::BranchExists(name.to_string()));
+        }
+        let id = ObjectId::from_hex(commit.as_bytes()).map_err(backend_error)?;
+        self.repo
+            .reference(
+                refname.as_str(),
+                id,
+                PreviousValue::MustNotExist,
+                format!("branch: Created from {}", commit),
+            )
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    fn delete_branch(&self, name: &str) -> Result<()> {
+        let refname = format!("refs/heads/{}", name);
+        let reference = self
+            .find_reference(refname.as_bytes())?
+            .ok_or_else(|| BranchStackError::InvalidBranchName(name.to_string()))?;
+        reference.delete().map_err(backend_error)?;
+        // Only the last section with a name is removed at a time, and the
+        // branch's settings may be split across several.
+        self.edit_config(|config| {
+            while config.remove_section("branch", name).is_some() {}
+            Ok(())
+        })
+    }
+
+    fn delete_ref(&self, refname: &str) -> Result<()> {
+        if let Some(reference) = self.find_reference(refname.as_bytes())? {
+            reference.delete().map_err(backend_error)?;
+        }
+        Ok(())
+    }
+
+    fn config_string(&self, key: &str) -> Result<Option<String>> {
+        let value = self.config.borrow().string(key);
+        Ok(value.map(|value| value.to_str_lossy().into_owned()))
+    }
+
+    fn config_bool(&self, key: &str) -> Result<Option<bool>> {
+        self.config.borrow().boolean(key).map_err(backend_error)
+    }
+
+    fn set_config(&self, key: &str, value: &str) -> Result<()> {
+        self.edit_config(|config| {
+            config.set_raw_value(key, value).map_err(backend_error)?;
+            Ok(())
+        })
+    }
+
+    fn read_ref_file(&self, refname: &str, filename: &str) -> Result<Option<Vec<u8>>> {
+        let mut reference = match self.find_reference(refname.as_bytes())? {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+        let tree = reference
+            .peel_to_commit()
+            .map_err(backend_error)?
+            .tree()
+            .map_err(backend_error)?;
+        let entry = tree
+            .lookup_entry_by_path(filename)
+            .map_err(backend_error)?
+            .ok_or_else(|| {
+                BranchStackError::BackendError(format!("{} has no {}", refname, filename))
+            })?;
+        let blob = entry.object().map_err(backend_error)?;
+        Ok(Some(blob.detach().data))
+    }
+
+    fn commit_ref_file(
+        &self,
+        refname: &str,
+        filename: &str,
+        contents: &[u8],
+        message: &str,
+    ) -> Result<String> {
+        let blob = self.repo.write_blob(contents).map_err(backend_error)?;
+        let mut editor = self
+            .repo
+            .edit_tree(ObjectId::empty_tree(self.repo.object_hash()))
+            .map_err(backend_error)?;
+        editor
+            .upsert(filename, EntryKind::Blob, blob)
+            .map_err(backend_error)?;
+        let tree = editor.write().map_err(backend_error)?.detach();
+
+        let parent = match self.find_reference(refname.as_bytes())? {
+            Some(mut reference) => Some(reference.peel_to_commit().map_err(backend_error)?),
+            None => None,
+        };
+        if let Some(ref parent) = parent {
+            if parent.tree_id().map_err(backend_error)? == tree {
+                return Ok(parent.id.to_string());
+            }
+        }
+
+        let signature = self.signature()?;
+        let parents: Vec<ObjectId> = parent.iter().map(|parent| parent.id).collect();
+        let id = self
+            .repo
+            .commit_as(signature, signature, refname, message, tree, parents)
+            .map_err(backend_error)?;
+        Ok(id.to_string())
+    }
+
+    fn is_dirty(&self) -> Result<bool> {
+        Ok(!self.changed_paths()?.is_empty())
+    }
+
+    fn stash_changes(&self, message: &str) -> Result<String> {
+        let head = self.repo.head_commit().map_err(backend_error)?;
+        let branch = match self.repo.head_name().map_err(backend_error)? {
+            Some(name) => name.shorten().to_str_lossy().into_owned(),
+            None => "(no branch)".to_string(),
+        };
+        let subject = head.message_raw_sloppy().lines().next().unwrap_or_default();
+        let summary = format!("{} {}", head.id.to_hex_with_len(7), subject.as_bstr());
+
+        // Like `git stash`, the index is saved in one commit, and the
+        // working tree in another on top of it and HEAD.
+        let index = self.repo.index_or_empty().map_err(backend_error)?;
+        let mut entries: HashMap<BString, (ObjectId, Mode)> = index
+            .entries()
+            .iter()
+            .map(|entry| (entry.path(&index).to_owned(), (entry.id, entry.mode)))
+            .collect();
+        let index_tree = self.write_tree(&entries)?;
+        let (mut pipeline, _) = self.repo.filter_pipeline(None).map_err(backend_error)?;
+        for path in self.changed_paths()? {
+            if !entries.contains_key(&path) {
+                continue;
+            }
+            match pipeline
+                .worktree_file_to_object(path.as_bstr(), &index)
+                .map_err(backend_error)?
+            {
+                Some((id, kind, _)) => {
+                    entries.insert(path, (id, Mode::from(EntryMode::from(kind))));
+                }
+                None => {
+                    entries.remove(&path);
+                }
+            }
+        }
+        let worktree_tree = self.write_tree(&entries)?;
+
+        let signature = self.signature()?;
+        let index_commit = self
+            .repo
+            .new_commit_as(
+                signature,
+                signature,
+                format!("index on {}: {}", branch, summary),
+                index_tree,
+                [head.id],
+            )
+            .map_err(backend_error)?
+            .id;
+        let message = format!("On {}: {}", branch, message);
+        let stash = self
+            .repo
+            .new_commit_as(
+                signature,
+                signature,
+                &message,
+                worktree_tree,
+                [head.id, index_commit],
+            )
+            .map_err(backend_error)?
+            .id;
+        self.repo
+            .edit_reference(RefEdit::update_with_log(
+                FullName::try_from(STASH_REF).map_err(backend_error)?,
+                Target::Object(stash),
+                PreviousValue::Any,
+                LogChange {
+                    mode: RefLog::AndReference,
+                    force_create_reflog: true,
+                    message: message.into(),
+                },
+            ))
+            .map_err(backend_error)?;
+
+        self.checkout_tree(head.id, true)?;
+        Ok(stash.to_string())
+    }
+
+    fn pop_stash(&self, stash: &str) -> Result<()> {
+        let log_path = self.repo.common_dir().join("logs").join(STASH_REF);
+        let log = match read(&log_path) {
+            Ok(log) => log,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        // Each line of the stash's reflog starts with the ids it moved
+        // from and to, and the newest one is last.
+        let mut lines: Vec<&[u8]> = log.lines_with_terminator().collect();
+        let new_id = |line: &[u8]| line.split_str(" ").nth(1).map(<[u8]>::to_vec);
+        let position = match lines
+            .iter()
+            .rposition(|line| new_id(line).as_deref() == Some(stash.as_bytes()))
+        {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        self.apply_stash(ObjectId::from_hex(stash.as_bytes()).map_err(backend_error)?)?;
+
+        lines.remove(position);
+        let top = match lines.last() {
+            Some(top) => new_id(top).unwrap_or_default(),
+            None => return self.delete_ref(STASH_REF),
+        };
+        let top = ObjectId::from_hex(&top).map_err(backend_error)?;
+        self.repo
+            .edit_reference(RefEdit::update(
+                FullName::try_from(STASH_REF).map_err(backend_error)?,
+                Target::Object(top),
+                PreviousValue::Any,
+                "",
+            ))
+            .map_err(backend_error)?;
+        write_atomic(&log_path, &lines.concat())
+    }
+
+    fn submodules(&self) -> Result<Vec<(PathBuf, String)>> {
+        let index = self.repo.index_or_empty().map_err(backend_error)?;
+        Ok(index
+            .entries()
+            .iter()
+            .filter(|entry| entry.mode.is_submodule())
+            .map(|entry| {
+                let path = entry.path(&index).to_path_lossy().into_owned();
+                (path, entry.id.to_string())
+            })
+            .collect())
+    }
+
+    fn fetch(&self) -> Result<()> {
+        Err(BranchStackError::BackendError(
+            "the gix backend can't fetch; run `git fetch` first".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir, read_to_string, remove_dir_all, remove_file, write};
+    use std::path::Path;
+
+    use git2::{Repository, Signature, Status};
+    use spectral::prelude::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::GixBackend;
+    use crate::backend::GitBackend;
+    use crate::errors::BranchStackError;
+    use crate::git::ConflictReason;
+    use crate::stack::StackEntry;
+
+    /// A repository with `file-1` on master, and `file-2` instead on
+    /// `branch-2`. Both have `common`.
+    fn setup_repo() -> (TempDir, Repository) {
+        let working_dir = tempdir().unwrap();
+        let repo = Repository::init(working_dir.path()).unwrap();
+        let sig = Signature::now("Zaphod Beeblebrox", "zbeebleb@heartofgold.ship").unwrap();
+        {
+            let mut index = repo.index().unwrap();
+            write(working_dir.path().join("common"), "common\n").unwrap();
+            index.add_path("common".as_ref()).unwrap();
+            write(working_dir.path().join("file-1"), "first\n").unwrap();
+            index.add_path("file-1".as_ref()).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let first = repo
+                .commit(Some("HEAD"), &sig, &sig, "commit 1", &tree, &[])
+                .unwrap();
+            let first = repo.find_commit(first).unwrap();
+
+            index.remove_path("file-1".as_ref()).unwrap();
+            write(working_dir.path().join("file-2"), "second\n").unwrap();
+            index.add_path("file-2".as_ref()).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let second = repo
+                .commit(None, &sig, &sig, "commit 2", &tree, &[&first])
+                .unwrap();
+            repo.branch("branch-2", &repo.find_commit(second).unwrap(), false)
+                .unwrap();
+
+            // put the index and working tree back the way master has them
+            let master = repo.revparse_single("master").unwrap();
+            repo.reset(&master, git2::ResetType::Hard, None).unwrap();
+            let _ = std::fs::remove_file(working_dir.path().join("file-2"));
+        }
+        (working_dir, repo)
+    }
+
+    /// The branch HEAD is on, read with git2.
+    fn head_branch(repo: &Repository) -> String {
+        repo.head().unwrap().shorthand().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_gix_backend_switches_branches() {
+        let (working_dir, repo) = setup_repo();
+        let backend = GixBackend::open(working_dir.path()).unwrap();
+
+        backend
+            .checkout_entry(&StackEntry::new("branch-2"), false)
+            .unwrap();
+        assert_that(&head_branch(&repo)).is_equal_to("branch-2".to_string());
+        assert_that(&working_dir.path().join("file-1")).does_not_exist();
+        assert_that(&working_dir.path().join("file-2")).exists();
+
+        let reflog = repo.reflog("HEAD").unwrap();
+        assert_that(&reflog.get(0).unwrap().message().unwrap())
+            .is_equal_to("checkout: moving from master to branch-2");
+
+        let statuses = repo.statuses(None).unwrap();
+        assert_that(&statuses.len()).is_equal_to(0);
+    }
+
+    #[test]
+    fn test_gix_backend_refuses_to_overwrite_untracked_files() {
+        let (working_dir, repo) = setup_repo();
+        let backend = GixBackend::open(working_dir.path()).unwrap();
+        write(working_dir.path().join("file-2"), "mine\n").unwrap();
+
+        let result = backend.checkout_entry(&StackEntry::new("branch-2"), false);
+        assert_that(&result)
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::CheckoutConflicts(_)));
+        assert_that(&head_branch(&repo)).is_equal_to("master".to_string());
+
+        backend
+            .checkout_entry(&StackEntry::new("branch-2"), true)
+            .unwrap();
+        assert_that(&head_branch(&repo)).is_equal_to("branch-2".to_string());
+    }
+
+    #[test]
+    fn test_gix_backend_carries_changes() {
+        let (working_dir, repo) = setup_repo();
+        let backend = GixBackend::open(working_dir.path()).unwrap();
+        write(working_dir.path().join("common"), "changed\n").unwrap();
+        write(working_dir.path().join("file-3"), "third\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path("file-3".as_ref()).unwrap();
+        index.write().unwrap();
+
+        backend
+            .checkout_entry(&StackEntry::new("branch-2"), false)
+            .unwrap();
+        assert_that(&head_branch(&repo)).is_equal_to("branch-2".to_string());
+        assert_that(&read_to_string(working_dir.path().join("common")).unwrap())
+            .is_equal_to("changed\n".to_string());
+
+        let status = |path: &str| repo.status_file(path.as_ref()).unwrap();
+        assert_that(&status("common")).is_equal_to(Status::WT_MODIFIED);
+        assert_that(&status("file-3")).is_equal_to(Status::INDEX_NEW);
+        assert_that(&status("file-2")).is_equal_to(Status::CURRENT);
+    }
+
+    #[test]
+    fn test_gix_backend_reports_changes_that_would_be_lost() {
+        let (working_dir, repo) = setup_repo();
+        let backend = GixBackend::open(working_dir.path()).unwrap();
+        write(working_dir.path().join("file-1"), "changed\n").unwrap();
+
+        let result = backend.checkout_entry(&StackEntry::new("branch-2"), false);
+        assert_that(&result).is_err().matches(|v| match v {
+            BranchStackError::CheckoutConflicts(conflicts) => {
+                conflicts.len() == 1
+                    && conflicts[0].path == Path::new("file-1")
+                    && conflicts[0].reason == ConflictReason::Deleted
+            }
+            _ => false,
+        });
+        assert_that(&head_branch(&repo)).is_equal_to("master".to_string());
+
+        backend
+            .checkout_entry(&StackEntry::new("branch-2"), true)
+            .unwrap();
+        assert_that(&head_branch(&repo)).is_equal_to("branch-2".to_string());
+        assert_that(&working_dir.path().join("file-1")).does_not_exist();
+        let statuses = repo.statuses(None).unwrap();
+        assert_that(&statuses.len()).is_equal_to(0);
+    }
+
+    #[test]
+    fn test_gix_backend_keeps_files_when_the_checkout_fails() {
+        let (working_dir, repo) = setup_repo();
+        let backend = GixBackend::open(working_dir.path()).unwrap();
+        let tree = repo.revparse_single("branch-2^{tree}").unwrap();
+        let blob = tree.as_tree().unwrap().get_name("file-2").unwrap().id();
+        let blob = blob.to_string();
+        remove_file(
+            repo.path()
+                .join("objects")
+                .join(&blob[..2])
+                .join(&blob[2..]),
+        )
+        .unwrap();
+
+        let result = backend.checkout_entry(&StackEntry::new("branch-2"), false);
+        assert_that(&result)
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::BackendError(_)));
+        assert_that(&head_branch(&repo)).is_equal_to("master".to_string());
+        assert_that(&working_dir.path().join("file-1")).exists();
+    }
+
+    #[test]
+    fn test_gix_backend_replaces_files_with_directories() {
+        let (working_dir, repo) = setup_repo();
+        let sig = Signature::now("Zaphod Beeblebrox", "zbeebleb@heartofgold.ship").unwrap();
+        {
+            let mut index = repo.index().unwrap();
+            index.remove_path("file-1".as_ref()).unwrap();
+            remove_file(working_dir.path().join("file-1")).unwrap();
+            create_dir(working_dir.path().join("file-1")).unwrap();
+            write(working_dir.path().join("file-1/nested"), "nested\n").unwrap();
+            index.add_path("file-1/nested".as_ref()).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let head = repo.head().unwrap().peel_to_commit().unwrap();
+            let commit = repo
+                .commit(None, &sig, &sig, "nest file-1", &tree, &[&head])
+                .unwrap();
+            repo.branch("nested", &repo.find_commit(commit).unwrap(), false)
+                .unwrap();
+            remove_dir_all(working_dir.path().join("file-1")).unwrap();
+            repo.reset(head.as_object(), git2::ResetType::Hard, None)
+                .unwrap();
+        }
+        let backend = GixBackend::open(working_dir.path()).unwrap();
+
+        backend
+            .checkout_entry(&StackEntry::new("nested"), false)
+            .unwrap();
+        assert_that(&working_dir.path().join("file-1/nested")).is_a_file();
+        backend
+            .checkout_entry(&StackEntry::new("master"), false)
+            .unwrap();
+        assert_that(&working_dir.path().join("file-1")).is_a_file();
+        let statuses = repo.statuses(None).unwrap();
+        assert_that(&statuses.len()).is_equal_to(0);
+    }
+}
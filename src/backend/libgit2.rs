@@ -0,0 +1,417 @@
+/// # libgit2 Backend
+///
+/// This backend does everything in-process with libgit2. It's built with
+/// the `libgit2` cargo feature, which is on by default.
+use std::path::{Path, PathBuf};
+use std::str;
+
+use git2::build::CheckoutBuilder;
+use git2::{
+    BranchType, CheckoutNotificationType, Commit, DiffFile, ErrorCode, Object, ObjectType, Oid,
+    Reference, Repository, Signature, Status, StatusOptions,
+};
+
+use super::{BackendKind, GitBackend};
+use crate::errors::{BranchStackError, Result};
+use crate::git::{CheckoutConflict, ConflictReason};
+use crate::lock::write_atomic;
+use crate::stack::StackEntry;
+
+/// The backend that uses libgit2.
+pub struct Libgit2Backend {
+    repo: Repository,
+}
+
+impl Libgit2Backend {
+    /// Open the repository at `path`, or the one that `path` is in.
+    pub fn open(path: &Path) -> Result<Libgit2Backend> {
+        Ok(Libgit2Backend::new(Repository::discover(path)?))
+    }
+
+    /// Create a backend for `repo`.
+    pub fn new(repo: Repository) -> Libgit2Backend {
+        Libgit2Backend { repo }
+    }
+
+    /// Look up a reference, returning `None` if it doesn't exist or its
+    /// name isn't valid.
+    fn find_reference_opt(&self, refname: &str) -> Result<Option<Reference<'_>>> {
+        match self.repo.find_reference(refname) {
+            Ok(reference) => Ok(Some(reference)),
+            Err(ref err) if matches!(err.code(), ErrorCode::NotFound | ErrorCode::InvalidSpec) => {
+                Ok(None)
+            }
+            Err(err) => Err(BranchStackError::from(err)),
+        }
+    }
+
+    /// Look up a reference by the bytes of its full name. git2 only looks
+    /// refs up by `&str`, so a name that isn't valid UTF-8 is found by
+    /// going through all of them.
+    fn find_reference_bytes(&self, refname: &[u8]) -> Result<Option<Reference<'_>>> {
+        if let Ok(refname) = str::from_utf8(refname) {
+            return self.find_reference_opt(refname);
+        }
+        for reference in self.repo.references()? {
+            let reference = reference?;
+            if reference.name_bytes() == refname {
+                return Ok(Some(reference));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The signature from the git config, or a stand-in for this program if
+    /// the user's name and email aren't set.
+    fn default_signature(&self) -> Result<Signature<'static>> {
+        let signature = self
+            .repo
+            .signature()
+            .or_else(|_| Signature::now("git-branch-stack", "git-branch-stack@localhost"))?;
+        Ok(signature)
+    }
+
+    /// Check out the tree of `object`. Unless `force` is set, local changes
+    /// are kept, and if any of them are in the way, nothing is changed and
+    /// this returns `Err(BranchStackError::CheckoutConflicts)` listing each
+    /// path. With `force`, they're thrown away.
+    fn checkout_tree(&self, object: &Object<'_>, force: bool) -> Result<()> {
+        let mut conflicts = Vec::new();
+        let result = {
+            let mut checkout = CheckoutBuilder::default();
+            if force {
+                checkout.force();
+            }
+            checkout.notify_on(CheckoutNotificationType::CONFLICT);
+            checkout.notify(|_, path, baseline, target, _| {
+                let exists = |file: Option<DiffFile<'_>>| file.is_some_and(|f| !f.id().is_zero());
+                if let Some(path) = path {
+                    let reason = if !exists(baseline) {
+                        ConflictReason::Untracked
+                    } else if !exists(target) {
+                        ConflictReason::Deleted
+                    } else {
+                        ConflictReason::Modified
+                    };
+                    conflicts.push(CheckoutConflict {
+                        path: path.to_path_buf(),
+                        reason,
+                    });
+                }
+                true
+            });
+            self.repo.checkout_tree(object, Some(&mut checkout))
+        };
+
+        match result {
+            Err(_) if !conflicts.is_empty() => Err(BranchStackError::CheckoutConflicts(conflicts)),
+            result => Ok(result?),
+        }
+    }
+
+    fn switch_branch(&self, branch_name: &[u8], force: bool) -> Result<()> {
+        let refname = [b"refs/heads/".as_ref(), branch_name].concat();
+        let reference = self.find_reference_bytes(&refname)?.ok_or_else(|| {
+            BranchStackError::InvalidRevision(String::from_utf8_lossy(branch_name).into_owned())
+        })?;
+
+        let object = reference.peel(ObjectType::Commit)?;
+        self.checkout_tree(&object, force)?;
+
+        // `set_head` refuses a branch that's checked out in a linked
+        // worktree, after the tree has already been checked out. `switch`
+        // looks for that itself unless it's told not to, so HEAD is moved
+        // directly, with the reflog message `set_head` would have written.
+        let message = self.checkout_message(&String::from_utf8_lossy(branch_name))?;
+        match str::from_utf8(&refname) {
+            Ok(refname) => {
+                self.repo
+                    .reference_symbolic("HEAD", refname, true, &message)?;
+            }
+            Err(_) => self.set_head_bytes(&refname, object.id(), &message)?,
+        }
+
+        Ok(())
+    }
+
+    /// Put HEAD on the branch `refname`, which is at `oid`, when its name
+    /// isn't valid UTF-8. git2 only takes ref names as `&str`, so HEAD and
+    /// its reflog entry are written here instead.
+    fn set_head_bytes(&self, refname: &[u8], oid: Oid, message: &str) -> Result<()> {
+        let contents = [b"ref: ".as_ref(), refname, b"\n"].concat();
+        write_atomic(self.repo.path().join("HEAD"), &contents)?;
+
+        let mut reflog = self.repo.reflog("HEAD")?;
+        reflog.append(oid, &self.default_signature()?, Some(message))?;
+        reflog.write()?;
+        Ok(())
+    }
+
+    /// The reflog message for moving HEAD from where it is now to `to`, in
+    /// the form git uses: `checkout: moving from master to feature`. `git
+    /// checkout -` and `@{-1}` find the previous branch by parsing these,
+    /// so `to` should be the branch, tag, or revision as the user named it.
+    fn checkout_message(&self, to: &str) -> Result<String> {
+        let head = self.repo.find_reference("HEAD")?;
+        let from = match head.symbolic_target_bytes() {
+            Some(target) => {
+                let branch_name = target.strip_prefix(b"refs/heads/").unwrap_or(target);
+                String::from_utf8_lossy(branch_name).into_owned()
+            }
+            None => head.target().map(|oid| oid.to_string()).unwrap_or_default(),
+        };
+        Ok(format!("checkout: moving from {} to {}", from, to))
+    }
+}
+
+impl GitBackend for Libgit2Backend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Libgit2
+    }
+
+    fn git_dir(&self) -> &Path {
+        self.repo.path()
+    }
+
+    fn workdir(&self) -> Option<&Path> {
+        self.repo.workdir()
+    }
+
+    fn current_branch_name(&self) -> Result<Vec<u8>> {
+        let branch_name = self
+            .repo
+            .branches(Some(BranchType::Local))?
+            .filter_map(|try_branch| try_branch.ok())
+            .map(|pair| pair.0)
+            .filter(|branch| branch.is_head())
+            .find_map(|branch| branch.name_bytes().ok().map(<[u8]>::to_vec))
+            .ok_or(BranchStackError::NoCurrrentBranch)?;
+        Ok(branch_name)
+    }
+
+    fn head_commit(&self) -> Result<String> {
+        let head = self.repo.head().map_err(|err| match err.code() {
+            ErrorCode::UnbornBranch | ErrorCode::NotFound => BranchStackError::UnbornHead,
+            _ => BranchStackError::from(err),
+        })?;
+        Ok(head.peel_to_commit()?.id().to_string())
+    }
+
+    fn change_branch(&self, branch_name: &str) -> Result<()> {
+        self.switch_branch(branch_name.as_bytes(), false)
+    }
+
+    fn checkout_entry(&self, entry: &StackEntry, force: bool) -> Result<()> {
+        if entry.is_branch() {
+            return self.switch_branch(entry.name_bytes(), force);
+        }
+
+        let oid = match entry.refname_bytes() {
+            Some(refname) => self.find_ref(&refname)?,
+            None => Some(entry.name.clone()),
+        }
+        .ok_or_else(|| BranchStackError::InvalidRevision(entry.name.clone()))?;
+        let object = self
+            .repo
+            .find_object(Oid::from_str(&oid)?, Some(ObjectType::Commit))?;
+        self.checkout_tree(&object, force)?;
+
+        let message = self.checkout_message(&String::from_utf8_lossy(entry.reflog_name()))?;
+        self.repo.reference("HEAD", object.id(), true, &message)?;
+
+        Ok(())
+    }
+
+    fn find_ref(&self, refname: &[u8]) -> Result<Option<String>> {
+        match self.find_reference_bytes(refname)? {
+            Some(reference) => Ok(Some(reference.peel_to_commit()?.id().to_string())),
+            None => Ok(None),
+        }
+    }
+
+    fn refs(&self, prefix: &str) -> Result<Vec<(Vec<u8>, String)>> {
+        let mut refs = Vec::new();
+        for reference in self.repo.references()? {
+            let reference = reference?;
+            if !reference.name_bytes().starts_with(prefix.as_bytes()) {
+                continue;
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                refs.push((reference.name_bytes().to_vec(), commit.id().to_string()));
+            }
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    fn resolve_revision(&self, revision: &str) -> Result<(String, Option<Vec<u8>>)> {
+        let (object, reference) =
+            self.repo
+                .revparse_ext(revision)
+                .map_err(|err| match err.code() {
+                    ErrorCode::NotFound | ErrorCode::InvalidSpec | ErrorCode::Ambiguous => {
+                        BranchStackError::InvalidRevision(revision.to_string())
+                    }
+                    _ => BranchStackError::from(err),
+                })?;
+        let oid = object.peel_to_commit()?.id().to_string();
+        Ok((
+            oid,
+            reference.map(|reference| reference.name_bytes().to_vec()),
+        ))
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> Result<bool> {
+        let ancestor = Oid::from_str(ancestor)?;
+        let descendant = Oid::from_str(descendant)?;
+        Ok(ancestor == descendant || self.repo.graph_descendant_of(descendant, ancestor)?)
+    }
+
+    fn remotes(&self) -> Result<Vec<String>> {
+        let remotes = self.repo.remotes()?;
+        Ok(remotes.iter().flatten().map(str::to_string).collect())
+    }
+
+    fn create_branch(&self, name: &str, commit: &str) -> Result<()> {
+        let commit = self.repo.find_commit(Oid::from_str(commit)?)?;
+        self.repo
+            .branch(name, &commit, false)
+            .map_err(|err| match err.code() {
+                ErrorCode::Exists => BranchStackError::BranchExists(name.to_string()),
+                _ => BranchStackError::from(err),
+            })?;
+        Ok(())
+    }
+
+    fn delete_branch(&self, name: &str) -> Result<()> {
+        self.repo.find_branch(name, BranchType::Local)?.delete()?;
+        Ok(())
+    }
+
+    fn delete_ref(&self, refname: &str) -> Result<()> {
+        if let Some(mut reference) = self.find_reference_opt(refname)? {
+            reference.delete()?;
+        }
+        Ok(())
+    }
+
+    fn config_string(&self, key: &str) -> Result<Option<String>> {
+        match self.repo.config()?.get_string(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(ref err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(BranchStackError::from(err)),
+        }
+    }
+
+    fn config_bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.repo.config()?.get_bool(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(ref err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(BranchStackError::from(err)),
+        }
+    }
+
+    fn set_config(&self, key: &str, value: &str) -> Result<()> {
+        self.repo.config()?.set_str(key, value)?;
+        Ok(())
+    }
+
+    fn read_ref_file(&self, refname: &str, filename: &str) -> Result<Option<Vec<u8>>> {
+        let reference = match self.find_reference_opt(refname)? {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+        let tree = reference.peel_to_commit()?.tree()?;
+        let entry = tree.get_path(Path::new(filename))?;
+        let blob = self.repo.find_blob(entry.id())?;
+        Ok(Some(blob.content().to_vec()))
+    }
+
+    fn commit_ref_file(
+        &self,
+        refname: &str,
+        filename: &str,
+        contents: &[u8],
+        message: &str,
+    ) -> Result<String> {
+        let blob = self.repo.blob(contents)?;
+        let mut builder = self.repo.treebuilder(None)?;
+        builder.insert(filename, blob, 0o100_644)?;
+        let tree = self.repo.find_tree(builder.write()?)?;
+
+        let parent = match self.find_reference_opt(refname)? {
+            Some(reference) => Some(reference.peel_to_commit()?),
+            None => None,
+        };
+        if let Some(ref parent) = parent {
+            if parent.tree_id() == tree.id() {
+                return Ok(parent.id().to_string());
+            }
+        }
+
+        let signature = self.default_signature()?;
+        let parents: Vec<&Commit> = parent.iter().collect();
+        let oid = self.repo.commit(
+            Some(refname),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )?;
+        Ok(oid.to_string())
+    }
+
+    fn is_dirty(&self) -> Result<bool> {
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(false)
+            .include_ignored(false)
+            .exclude_submodules(true);
+        let statuses = self.repo.statuses(Some(&mut options))?;
+        let dirty = statuses
+            .iter()
+            .any(|status| status.status() != Status::CURRENT);
+        Ok(dirty)
+    }
+
+    fn stash_changes(&self, message: &str) -> Result<String> {
+        // Stashing needs a mutable repository.
+        let mut repo = Repository::open(self.repo.path())?;
+        let signature = self.default_signature()?;
+        let oid = repo.stash_save(&signature, message, None)?;
+        Ok(oid.to_string())
+    }
+
+    fn pop_stash(&self, stash: &str) -> Result<()> {
+        let mut repo = Repository::open(self.repo.path())?;
+        let stash_oid = Oid::from_str(stash)?;
+        let mut index = None;
+        repo.stash_foreach(|i, _message, oid| {
+            if *oid == stash_oid {
+                index = Some(i);
+            }
+            index.is_none()
+        })?;
+        match index {
+            Some(index) => Ok(repo.stash_pop(index, None)?),
+            None => Ok(()),
+        }
+    }
+
+    fn submodules(&self) -> Result<Vec<(PathBuf, String)>> {
+        let mut submodules = Vec::new();
+        for submodule in self.repo.submodules()? {
+            if let Some(oid) = submodule.index_id() {
+                submodules.push((submodule.path().to_path_buf(), oid.to_string()));
+            }
+        }
+        Ok(submodules)
+    }
+
+    fn fetch(&self) -> Result<()> {
+        let mut remote = self.repo.find_remote("origin")?;
+        remote.fetch(&[] as &[&str], None, None)?;
+        Ok(())
+    }
+}
@@ -7,9 +7,14 @@ use std::convert::From;
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 use std::result;
 
+#[cfg(feature = "libgit2")]
 use git2;
+use serde_json;
+
+use crate::git::CheckoutConflict;
 
 /// The type enumerating all of the possible error states.
 #[derive(Debug)]
@@ -19,17 +24,93 @@ pub enum BranchStackError {
     /// A command-line parameter value is invalid.
     ArgError(String),
     /// Errors from libgit2.
+    #[cfg(feature = "libgit2")]
     GitError(git2::Error),
     /// The branch to push doesn't exist.
     InvalidBranchName(String),
     /// Not currently on a branch.
     NoCurrrentBranch,
+    /// The revision to push doesn't name anything in the repository.
+    InvalidRevision(String),
+    /// More than one remote has a branch with this name, so it's not clear
+    /// which one a new local branch should track.
+    AmbiguousRemoteBranch(String, Vec<String>),
+    /// The branch to create already exists.
+    BranchExists(String),
+    /// The backend isn't `libgit2`, `git`, or `gix`.
+    InvalidBackend(String),
+    /// The backend wasn't built into this program.
+    BackendUnavailable(String),
+    /// An error from a backend that doesn't have a variant of its own.
+    BackendError(String),
+    /// A `git` command run by the `git` backend failed. This has the
+    /// command and what it printed to stderr.
+    GitCommandFailed(String, String),
+    /// A git hook couldn't be run or exited with an error.
+    HookFailed(String, String),
+    /// The dirty working tree policy isn't `abort`, `carry`, or `autostash`.
+    InvalidDirtyPolicy(String),
+    /// There are uncommitted changes, and the policy is to not switch.
+    DirtyWorkingTree,
+    /// The changes stashed on this entry couldn't be re-applied. This has
+    /// the entry and why.
+    StashConflict(String, String),
+    /// The repository is bare, so there's no working tree to switch.
+    BareRepository,
+    /// HEAD is on a branch that doesn't have any commits yet.
+    UnbornHead,
+    /// A merge is in progress.
+    MergeInProgress,
+    /// A rebase is in progress.
+    RebaseInProgress,
+    /// A cherry-pick is in progress.
+    CherryPickInProgress,
+    /// A revert is in progress.
+    RevertInProgress,
+    /// `git am` is in progress.
+    ApplyMailboxInProgress,
+    /// A bisect is in progress.
+    BisectInProgress,
+    /// The branch is already checked out in another worktree, at this path.
+    CheckedOutElsewhere(String, PathBuf),
+    /// These submodules have local changes, so they weren't updated.
+    SubmoduleChanges(Vec<PathBuf>),
+    /// Checking out would lose local changes to these paths.
+    CheckoutConflicts(Vec<CheckoutConflict>),
     /// IO errors. Probably unable to read from or write to the stack.
     IoError(io::Error),
     /// Trying to pop off an empty stack.
     EmptyStack,
     /// No such entry in the stack.
     NoStackEntry,
+    /// The stack file couldn't be parsed.
+    StackFormatError(serde_json::Error),
+    /// The stack file was written by a newer version of this program.
+    UnsupportedStackVersion(u32),
+    /// Another process holds the lock file.
+    Locked(PathBuf),
+    /// The journal file couldn't be parsed.
+    JournalFormatError(serde_json::Error),
+    /// There's no operation in the journal to undo.
+    NothingToUndo,
+    /// There's no undone operation in the journal to redo.
+    NothingToRedo,
+    /// An earlier operation was interrupted before it finished.
+    InterruptedOperation(String),
+    /// There's no interrupted operation to continue or abort.
+    NoInterruptedOperation,
+    /// The stack name can't be used.
+    InvalidStackName(String),
+    /// There's no stack with this name.
+    NoSuchStack(String),
+    /// A stack with this name already exists.
+    StackExists(String),
+    /// The stack is the current one, so it can't be deleted.
+    StackInUse(String),
+    /// The stack scope isn't `worktree` or `shared`.
+    InvalidStackScope(String),
+    /// The stack storage isn't `file` or `ref`.
+    InvalidStackStorage(String),
 }
 
 /// An alias to make working with these errors easier.
@@ -42,12 +123,161 @@ impl fmt::Display for BranchStackError {
         match self {
             InvalidCommandError => write!(f, "invalid command"),
             ArgError(ref arg_name) => write!(f, "invalid argument value: {}", arg_name),
+            #[cfg(feature = "libgit2")]
             GitError(ref err) => err.fmt(f),
             InvalidBranchName(ref name) => write!(f, "invalid branch name: {}", name),
             NoCurrrentBranch => write!(f, "no current branch"),
+            InvalidRevision(ref name) => write!(f, "unknown revision: {}", name),
+            BranchExists(ref name) => write!(f, "branch already exists: {}", name),
+            InvalidBackend(ref backend) => write!(
+                f,
+                "invalid backend: {} (expected `libgit2`, `git`, or `gix`)",
+                backend
+            ),
+            BackendUnavailable(ref backend) => write!(
+                f,
+                "the {} backend isn't available; build with `--features {}` to use it",
+                backend, backend
+            ),
+            BackendError(ref message) => write!(f, "{}", message),
+            GitCommandFailed(ref command, ref stderr) => {
+                write!(f, "`{}` failed: {}", command, stderr)
+            }
+            HookFailed(ref name, ref reason) => write!(f, "the {} hook failed: {}", name, reason),
+            InvalidDirtyPolicy(ref policy) => write!(
+                f,
+                "invalid dirty policy: {} (expected `abort`, `carry`, or `autostash`)",
+                policy
+            ),
+            DirtyWorkingTree => write!(
+                f,
+                "you have uncommitted changes; commit or stash them first, or \
+                 use `--dirty carry` or `--dirty autostash`"
+            ),
+            StashConflict(ref entry, ref err) => write!(
+                f,
+                "switched to {}, but the changes stashed there couldn't be \
+                 re-applied: {}. They're still in the stash; run `git stash pop` \
+                 once that's sorted out",
+                entry, err
+            ),
+            BareRepository => write!(
+                f,
+                "this is a bare repository, so there's no working tree to \
+                 switch branches in"
+            ),
+            UnbornHead => write!(
+                f,
+                "the current branch doesn't have any commits yet; make the first \
+                 commit before switching branches"
+            ),
+            MergeInProgress => write!(
+                f,
+                "a merge is in progress; finish it with `git commit` or \
+                 `git merge --continue`, or cancel it with `git merge --abort`"
+            ),
+            RebaseInProgress => write!(
+                f,
+                "a rebase is in progress; finish it with `git rebase --continue`, \
+                 or cancel it with `git rebase --abort`"
+            ),
+            CherryPickInProgress => write!(
+                f,
+                "a cherry-pick is in progress; finish it with \
+                 `git cherry-pick --continue`, or cancel it with \
+                 `git cherry-pick --abort`"
+            ),
+            RevertInProgress => write!(
+                f,
+                "a revert is in progress; finish it with `git revert --continue`, \
+                 or cancel it with `git revert --abort`"
+            ),
+            ApplyMailboxInProgress => write!(
+                f,
+                "`git am` is in progress; finish it with `git am --continue`, or \
+                 cancel it with `git am --abort`"
+            ),
+            BisectInProgress => write!(
+                f,
+                "a bisect is in progress; finish it with `git bisect reset` \
+                 before switching branches"
+            ),
+            CheckedOutElsewhere(ref name, ref path) => write!(
+                f,
+                "{} is already checked out at {}; use `--ignore-other-worktrees` \
+                 to check it out here anyway",
+                name,
+                path.display()
+            ),
+            SubmoduleChanges(ref paths) => write!(
+                f,
+                "these submodules have local changes, so they were left where \
+                 they were: {}",
+                paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            CheckoutConflicts(ref conflicts) => {
+                writeln!(f, "your local changes would be lost by switching branches:")?;
+                for conflict in conflicts {
+                    writeln!(f, "    {}", conflict)?;
+                }
+                write!(
+                    f,
+                    "commit or stash them first, or use `--force` to discard them"
+                )
+            }
+            AmbiguousRemoteBranch(ref name, ref remote_branches) => write!(
+                f,
+                "{} matches more than one remote-tracking branch ({}); push one \
+                 of them by its full name instead",
+                name,
+                remote_branches.join(", ")
+            ),
             IoError(ref err) => err.fmt(f),
             EmptyStack => write!(f, "empty stack"),
             NoStackEntry => write!(f, "no such entry in stack"),
+            StackFormatError(ref err) => write!(f, "invalid stack file: {}", err),
+            UnsupportedStackVersion(version) => {
+                write!(f, "unsupported stack file version: {}", version)
+            }
+            Locked(ref path) => write!(
+                f,
+                "unable to create '{}': file exists. Another git-branch-stack \
+                 process seems to be running. If not, remove the file and try again",
+                path.display()
+            ),
+            JournalFormatError(ref err) => write!(f, "invalid journal file: {}", err),
+            NothingToUndo => write!(f, "nothing to undo"),
+            NothingToRedo => write!(f, "nothing to redo"),
+            InterruptedOperation(ref action) => write!(
+                f,
+                "an earlier `{}` was interrupted, so the stack and the current \
+                 branch may not match. Run `git-branch-stack continue` to finish it, \
+                 or `git-branch-stack abort` to roll it back",
+                action
+            ),
+            NoInterruptedOperation => write!(f, "no interrupted operation in progress"),
+            InvalidStackName(ref name) => write!(f, "invalid stack name: {}", name),
+            NoSuchStack(ref name) => write!(f, "no such stack: {}", name),
+            StackExists(ref name) => write!(f, "stack already exists: {}", name),
+            StackInUse(ref name) => write!(
+                f,
+                "stack {} is the current stack; switch to another one before deleting it",
+                name
+            ),
+            InvalidStackScope(ref scope) => write!(
+                f,
+                "invalid stack scope: {} (expected `worktree` or `shared`)",
+                scope
+            ),
+            InvalidStackStorage(ref storage) => write!(
+                f,
+                "invalid stack storage: {} (expected `file` or `ref`)",
+                storage
+            ),
         }
     }
 }
@@ -57,16 +287,54 @@ impl error::Error for BranchStackError {
         match self {
             InvalidCommandError => "invalid command",
             ArgError(_) => "invalid argument value",
-            GitError(ref err) => err.description(),
+            #[cfg(feature = "libgit2")]
+            GitError(_) => "git error",
             InvalidBranchName(_) => "invalid branch name",
             NoCurrrentBranch => "no current branch",
-            IoError(ref err) => err.description(),
+            InvalidRevision(_) => "unknown revision",
+            BranchExists(_) => "branch already exists",
+            InvalidBackend(_) => "invalid backend",
+            BackendUnavailable(_) => "backend not available",
+            BackendError(_) => "backend error",
+            GitCommandFailed(_, _) => "git command failed",
+            HookFailed(_, _) => "hook failed",
+            InvalidDirtyPolicy(_) => "invalid dirty policy",
+            DirtyWorkingTree => "uncommitted changes",
+            StashConflict(_, _) => "unable to re-apply stash",
+            BareRepository => "bare repository",
+            UnbornHead => "no commits on the current branch",
+            MergeInProgress => "merge in progress",
+            RebaseInProgress => "rebase in progress",
+            CherryPickInProgress => "cherry-pick in progress",
+            RevertInProgress => "revert in progress",
+            ApplyMailboxInProgress => "git am in progress",
+            BisectInProgress => "bisect in progress",
+            CheckedOutElsewhere(_, _) => "branch checked out in another worktree",
+            SubmoduleChanges(_) => "submodules have local changes",
+            CheckoutConflicts(_) => "local changes would be lost",
+            AmbiguousRemoteBranch(_, _) => "ambiguous remote-tracking branch",
+            IoError(_) => "IO error",
             EmptyStack => "empty stack",
             NoStackEntry => "no such entry in stack",
+            StackFormatError(_) => "invalid stack file",
+            UnsupportedStackVersion(_) => "unsupported stack file version",
+            Locked(_) => "unable to lock file",
+            JournalFormatError(_) => "invalid journal file",
+            NothingToUndo => "nothing to undo",
+            NothingToRedo => "nothing to redo",
+            InterruptedOperation(_) => "an earlier operation was interrupted",
+            NoInterruptedOperation => "no interrupted operation in progress",
+            InvalidStackName(_) => "invalid stack name",
+            NoSuchStack(_) => "no such stack",
+            StackExists(_) => "stack already exists",
+            StackInUse(_) => "stack is in use",
+            InvalidStackScope(_) => "invalid stack scope",
+            InvalidStackStorage(_) => "invalid stack storage",
         }
     }
 }
 
+#[cfg(feature = "libgit2")]
 impl From<git2::Error> for BranchStackError {
     fn from(err: git2::Error) -> Self {
         GitError(err)
@@ -78,3 +346,9 @@ impl From<io::Error> for BranchStackError {
         IoError(err)
     }
 }
+
+impl From<serde_json::Error> for BranchStackError {
+    fn from(err: serde_json::Error) -> Self {
+        StackFormatError(err)
+    }
+}
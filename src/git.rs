@@ -1,67 +1,410 @@
 /// # git Utilities
 ///
-/// These are a set of higher-level functions for common operations.
-use git2::build::CheckoutBuilder;
-use git2::{BranchType, ObjectType, Repository};
+/// These are a set of higher-level functions for common operations. They
+/// work on any `GitBackend`.
+use std::fmt;
+use std::fs::{read, read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+use std::str;
 
+use crate::backend::{open_backend, GitBackend};
 use crate::errors::{BranchStackError, Result};
+use crate::stack::{EntryKind, StackEntry};
 
 /// This returns the name of the current branch. If the user's not on a named
-/// branch, this returns `Err(BranchStackError::NoCurrentBranch)`.
+/// branch, this returns `Err(BranchStackError::NoCurrentBranch)`. A name
+/// that isn't valid UTF-8 is converted lossily, so this is only for showing
+/// to the user; use `GitBackend::current_branch_name` to look the branch up.
 ///
 /// TODO: it may make more sense for this to return an `Option<String>` that
 /// is `None` if the user's not on a named branch.
-pub fn get_current_branch_name(repo: &Repository) -> Result<String> {
-    let branch_name = repo
-        .branches(Some(BranchType::Local))?
-        .filter_map(|try_branch| try_branch.ok())
-        .map(|pair| pair.0)
-        .filter(|branch| branch.is_head())
-        .filter_map(|branch| branch.name().ok().map(|b| b.map(String::from)))
-        .filter_map(|branch_name| branch_name)
-        .nth(0)
-        .ok_or(BranchStackError::NoCurrrentBranch)?;
-    Ok(branch_name)
-}
-
-/// Change to the branch named.
+pub fn get_current_branch_name(repo: &dyn GitBackend) -> Result<String> {
+    let branch_name = repo.current_branch_name()?;
+    Ok(String::from_utf8_lossy(&branch_name).into_owned())
+}
+
+/// Fail if the repository is in no state to switch branches: it's bare,
+/// HEAD is on a branch with no commits yet, or a merge, rebase,
+/// cherry-pick, revert, `git am`, or bisect is in progress.
 ///
-/// Currently this is implemented using `Repository.reset`. That's probably
-/// not right.
-pub fn change_branch(repo: &Repository, branch_name: &str) -> Result<()> {
-    let branch = repo.find_branch(branch_name, BranchType::Local)?;
-    let reference = branch.get();
-    let refname = reference
-        .name()
+/// What's in progress is read from the files git leaves in the git
+/// directory, the same way git and libgit2 work it out.
+pub fn check_repo_state(repo: &dyn GitBackend) -> Result<()> {
+    if repo.workdir().is_none() {
+        return Err(BranchStackError::BareRepository);
+    }
+    repo.head_commit()?;
+
+    let git_dir = repo.git_dir();
+    let exists = |path: &str| git_dir.join(path).exists();
+    if exists("rebase-merge") {
+        Err(BranchStackError::RebaseInProgress)
+    } else if exists("rebase-apply") {
+        if exists("rebase-apply/applying") {
+            Err(BranchStackError::ApplyMailboxInProgress)
+        } else {
+            Err(BranchStackError::RebaseInProgress)
+        }
+    } else if exists("MERGE_HEAD") {
+        Err(BranchStackError::MergeInProgress)
+    } else if exists("REVERT_HEAD") {
+        Err(BranchStackError::RevertInProgress)
+    } else if exists("CHERRY_PICK_HEAD") {
+        Err(BranchStackError::CherryPickInProgress)
+    } else if exists("BISECT_LOG") {
+        Err(BranchStackError::BisectInProgress)
+    } else {
+        Ok(())
+    }
+}
+
+/// This creates a stack entry for HEAD, recording the commit it points to.
+/// If HEAD is on a branch, that's a branch entry. If it's detached, it's an
+/// entry for the commit.
+pub fn get_head_entry(repo: &dyn GitBackend) -> Result<StackEntry> {
+    let oid = repo.head_commit()?;
+    match repo.current_branch_name() {
+        Ok(branch_name) => Ok(StackEntry::from_bytes(&branch_name).with_head(oid)),
+        Err(BranchStackError::NoCurrrentBranch) => Ok(StackEntry::commit(oid)),
+        Err(err) => Err(err),
+    }
+}
+
+/// This creates a stack entry for HEAD, stamped with the current time.
+pub fn get_current_entry(repo: &dyn GitBackend) -> Result<StackEntry> {
+    Ok(get_head_entry(repo)?.pushed_now())
+}
+
+/// This works out what `name` refers to and creates a stack entry for it.
+/// Local branches are tried first, then tags, then remote-tracking
+/// branches. Anything else is read as a revision the way `git rev-parse`
+/// would, so `HEAD~3`, `@{-1}`, and short commit ids work too. If the
+/// revision resolves to one of those refs, the entry is for that ref.
+/// Otherwise it's an entry for the commit, and checking it out detaches
+/// HEAD there; the entry keeps `name` for the reflog. If nothing matches, this returns
+/// `Err(BranchStackError::InvalidRevision)`.
+pub fn find_entry(repo: &dyn GitBackend, name: &str) -> Result<StackEntry> {
+    if let Some(entry) = find_ref_entry(repo, name.as_bytes())? {
+        return Ok(entry);
+    }
+
+    let (oid, refname) = repo.resolve_revision(name)?;
+    let entry = refname
+        .as_deref()
+        .and_then(entry_for_refname)
+        .unwrap_or_else(|| {
+            let entry = StackEntry::commit(oid.clone());
+            if name == oid {
+                entry
+            } else {
+                entry.with_revision(name)
+            }
+        });
+    Ok(entry.with_head(oid))
+}
+
+/// This works out what `name`, given as the bytes of a ref name, refers to,
+/// the same way `find_entry` does. A name that isn't valid UTF-8 can't be a
+/// revision like `HEAD~3`, so it's only looked for as a local branch, a
+/// tag, or a remote-tracking branch.
+pub fn find_entry_bytes(repo: &dyn GitBackend, name: &[u8]) -> Result<StackEntry> {
+    if let Ok(name) = str::from_utf8(name) {
+        return find_entry(repo, name);
+    }
+
+    find_ref_entry(repo, name)?.ok_or_else(|| {
+        BranchStackError::InvalidRevision(String::from_utf8_lossy(name).into_owned())
+    })
+}
+
+/// The entry for the local branch, tag, or remote-tracking branch called
+/// `name`, trying them in that order, or `None` if there isn't one.
+fn find_ref_entry(repo: &dyn GitBackend, name: &[u8]) -> Result<Option<StackEntry>> {
+    for kind in &[EntryKind::Branch, EntryKind::Tag, EntryKind::Remote] {
+        let entry = StackEntry::from_bytes(name).with_kind(*kind);
+        let refname = entry.refname_bytes().unwrap_or_default();
+        if let Some(oid) = repo.find_ref(&refname)? {
+            return Ok(Some(entry.with_head(oid)));
+        }
+    }
+    Ok(None)
+}
+
+/// The entry for a full ref name, if it's a local branch, a tag, or a
+/// remote-tracking branch.
+fn entry_for_refname(refname: &[u8]) -> Option<StackEntry> {
+    [EntryKind::Branch, EntryKind::Tag, EntryKind::Remote]
+        .iter()
+        .find_map(|kind| {
+            let name = refname.strip_prefix(kind.ref_prefix()?.as_bytes())?;
+            Some(StackEntry::from_bytes(name).with_kind(*kind))
+        })
+}
+
+/// The remote-tracking branches named `<remote>/<name>`, for each
+/// configured remote.
+pub fn find_remote_branches(repo: &dyn GitBackend, name: &str) -> Result<Vec<String>> {
+    let mut matches = Vec::new();
+    for remote in repo.remotes()? {
+        let remote_branch = format!("{}/{}", remote, name);
+        let refname = format!("refs/remotes/{}", remote_branch);
+        if repo.find_ref(refname.as_bytes())?.is_some() {
+            matches.push(remote_branch);
+        }
+    }
+    Ok(matches)
+}
+
+/// The remote-tracking branch to create the local branch `name` from, the
+/// way `git switch` does when there's no local branch yet. If no remote
+/// has a branch called `name`, this returns
+/// `Err(BranchStackError::InvalidRevision)`; if more than one does, it
+/// returns `Err(BranchStackError::AmbiguousRemoteBranch)`.
+pub fn remote_branch_to_track(repo: &dyn GitBackend, name: &str) -> Result<StackEntry> {
+    let mut remote_branches = find_remote_branches(repo, name)?;
+    if remote_branches.len() > 1 {
+        return Err(BranchStackError::AmbiguousRemoteBranch(
+            name.to_string(),
+            remote_branches,
+        ));
+    }
+    let remote_branch = remote_branches
+        .pop()
+        .ok_or_else(|| BranchStackError::InvalidRevision(name.to_string()))?;
+    Ok(StackEntry::new(remote_branch).with_kind(EntryKind::Remote))
+}
+
+/// Create the local branch `name` at the commit that `start` refers to. If
+/// `start` is a remote-tracking branch, it's set as the new branch's
+/// upstream, the way `git branch` does by default. If there's already a
+/// branch called `name`, this returns `Err(BranchStackError::BranchExists)`.
+pub fn create_branch(repo: &dyn GitBackend, name: &str, start: &StackEntry) -> Result<StackEntry> {
+    let oid = entry_commit(repo, start)?;
+    repo.create_branch(name, &oid)?;
+    if start.kind == EntryKind::Remote {
+        set_upstream(repo, name, &start.name)?;
+    }
+
+    Ok(StackEntry::new(name).with_head(oid))
+}
+
+/// Make the remote-tracking branch `upstream`, such as `origin/feature`,
+/// the upstream of the local branch `name`. The remote is the longest
+/// configured one that `upstream` starts with, since remote names can have
+/// slashes in them too.
+fn set_upstream(repo: &dyn GitBackend, name: &str, upstream: &str) -> Result<()> {
+    let (remote, branch) = repo
+        .remotes()?
+        .into_iter()
+        .filter_map(|remote| {
+            let branch = upstream.strip_prefix(&remote)?.strip_prefix('/')?;
+            Some((remote.clone(), branch.to_string()))
+        })
+        .max_by_key(|(remote, _)| remote.len())
+        .ok_or_else(|| BranchStackError::InvalidRevision(upstream.to_string()))?;
+    repo.set_config(&format!("branch.{}.remote", name), &remote)?;
+    repo.set_config(
+        &format!("branch.{}.merge", name),
+        &format!("refs/heads/{}", branch),
+    )
+}
+
+/// A name for a temporary branch at `start` that isn't used yet. It's
+/// `tmp/` followed by the entry's name, or by a short commit id for commit
+/// entries, with a number added if that's taken.
+pub fn temp_branch_name(repo: &dyn GitBackend, start: &StackEntry) -> Result<String> {
+    let base = match start.kind {
+        EntryKind::Commit => start.name.chars().take(7).collect(),
+        _ => start
+            .name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '/' | '-' | '_' | '.' => c,
+                _ => '-',
+            })
+            .collect::<String>(),
+    };
+    let base = format!("tmp/{}", base);
+
+    let mut name = base.clone();
+    let mut n = 1;
+    while repo
+        .find_ref(format!("refs/heads/{}", name).as_bytes())?
+        .is_some()
+    {
+        n += 1;
+        name = format!("{}-{}", base, n);
+    }
+    Ok(name)
+}
+
+/// Can the tip of the local branch `branch_name` be reached from another
+/// branch, a tag, or a remote-tracking branch? If so, deleting the branch
+/// doesn't lose any commits. Other refs, like `refs/stash` or the stacks
+/// under `refs/branch-stack/`, don't count: a stash made on top of the
+/// branch would otherwise make it look safe to delete.
+pub fn is_reachable_elsewhere(repo: &dyn GitBackend, branch_name: &str) -> Result<bool> {
+    let refname = format!("refs/heads/{}", branch_name);
+    let tip = repo
+        .find_ref(refname.as_bytes())?
         .ok_or_else(|| BranchStackError::InvalidBranchName(branch_name.to_string()))?;
 
-    let object = reference.peel(ObjectType::Commit)?;
-    let mut checkout = CheckoutBuilder::default();
-    repo.checkout_tree(&object, Some(&mut checkout))?;
+    for kind in &[EntryKind::Branch, EntryKind::Tag, EntryKind::Remote] {
+        let prefix = kind.ref_prefix().unwrap_or_default();
+        for (name, oid) in repo.refs(prefix)? {
+            if name == refname.as_bytes() {
+                continue;
+            }
+            if repo.is_ancestor(&tip, &oid)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
 
-    repo.set_head(&refname)?;
+/// The commit that a stack entry refers to now. For commit entries, that's
+/// the commit itself; for everything else, it's wherever the ref points.
+pub fn entry_commit(repo: &dyn GitBackend, entry: &StackEntry) -> Result<String> {
+    let oid = match entry.refname_bytes() {
+        Some(refname) => repo.find_ref(&refname)?,
+        None => Some(repo.resolve_revision(&entry.name)?.0),
+    };
+    oid.ok_or_else(|| BranchStackError::InvalidRevision(entry.name.clone()))
+}
 
-    Ok(())
+/// Is HEAD already at `entry`? For a branch entry, HEAD has to be on that
+/// branch. For anything else, it has to be detached at the entry's commit.
+pub fn is_checked_out(repo: &dyn GitBackend, entry: &StackEntry) -> Result<bool> {
+    let head = get_head_entry(repo)?;
+    if entry.is_branch() {
+        Ok(head.is_branch() && head.name_bytes() == entry.name_bytes())
+    } else {
+        let oid = entry_commit(repo, entry)?;
+        Ok(!head.is_branch() && head.head == Some(oid))
+    }
+}
+
+/// The git directory shared by all of the repository's worktrees. For a
+/// linked worktree, this is the main repository's git directory. Otherwise
+/// it's the same as `repo.git_dir()`.
+pub fn common_dir(repo: &dyn GitBackend) -> Result<PathBuf> {
+    let commondir_file = repo.git_dir().join("commondir");
+    if commondir_file.is_file() {
+        let contents = read_to_string(&commondir_file)?;
+        Ok(repo.git_dir().join(contents.trim()))
+    } else {
+        Ok(repo.git_dir().to_path_buf())
+    }
+}
+
+/// Find another worktree of the repository that has the branch
+/// `branch_name` checked out, and return the path to it.
+///
+/// Not every backend lists worktrees for us, so this reads the HEAD of the
+/// main worktree and of each linked worktree under `worktrees/` in the
+/// common git directory.
+pub fn find_other_worktree(repo: &dyn GitBackend, branch_name: &[u8]) -> Result<Option<PathBuf>> {
+    let common_dir = common_dir(repo)?;
+    let own_git_dir = repo.git_dir().canonicalize()?;
+    let head_ref = [b"ref: refs/heads/".as_ref(), branch_name].concat();
+
+    let mut git_dirs = vec![common_dir.clone()];
+    if let Ok(entries) = read_dir(common_dir.join("worktrees")) {
+        for entry in entries {
+            git_dirs.push(entry?.path());
+        }
+    }
+
+    for git_dir in git_dirs {
+        if git_dir.canonicalize()? == own_git_dir {
+            continue;
+        }
+        let head = match read(git_dir.join("HEAD")) {
+            Ok(head) => head,
+            Err(_) => continue,
+        };
+        if head.trim_ascii() != head_ref.as_slice() {
+            continue;
+        }
+        if git_dir == common_dir {
+            // A bare repository's HEAD isn't checked out anywhere.
+            let main = open_backend(&git_dir, repo.kind())?;
+            if let Some(path) = main.workdir() {
+                return Ok(Some(path.to_path_buf()));
+            }
+            continue;
+        }
+        // A linked worktree's `gitdir` file names the `.git` file in it.
+        let gitdir = read_to_string(git_dir.join("gitdir"))?;
+        let path = PathBuf::from(gitdir.trim());
+        let path = path.parent().map(Path::to_path_buf).unwrap_or(path);
+        return Ok(Some(path));
+    }
+    Ok(None)
+}
+
+/// Why a path can't be checked out without losing local changes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictReason {
+    /// The file has local changes that would be overwritten.
+    Modified,
+    /// The file has local changes, and it would be deleted.
+    Deleted,
+    /// The file is untracked, and it would be overwritten.
+    Untracked,
+}
+
+impl fmt::Display for ConflictReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictReason::Modified => write!(f, "local changes would be overwritten"),
+            ConflictReason::Deleted => write!(f, "local changes would be lost by deleting it"),
+            ConflictReason::Untracked => write!(f, "untracked file would be overwritten"),
+        }
+    }
+}
+
+/// A path that conflicts with a checkout.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckoutConflict {
+    /// The path, relative to the working tree.
+    pub path: PathBuf,
+    /// Why it conflicts.
+    pub reason: ConflictReason,
+}
+
+impl fmt::Display for CheckoutConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.reason)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::File;
     use std::io::{Read, Write};
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
     use git2::build::CheckoutBuilder;
-    use git2::{Commit, Error, ObjectType, Repository, ResetType, Signature};
+    use git2::{BranchType, Commit, Error, ObjectType, Oid, Repository, ResetType, Signature};
     use lipsum::lipsum;
     use spectral::prelude::*;
     use tempfile::{tempdir, TempDir};
 
-    use super::change_branch;
+    use super::{
+        check_repo_state, create_branch, find_entry, get_head_entry, is_checked_out,
+        is_reachable_elsewhere, remote_branch_to_track, temp_branch_name, CheckoutConflict,
+        ConflictReason,
+    };
+    use crate::backend::{open_backend, BackendKind, GitBackend};
+    use crate::errors::BranchStackError;
+    use crate::stack::{EntryKind, StackEntry};
 
     #[test]
     fn test_change_branch_creates_missing_files() {
         let (working_dir, repo) = setup_repo();
-        change_branch(&repo, "master").unwrap();
+        open(&repo).change_branch("master").unwrap();
         let file2 = working_dir.path().join("file-2");
         assert_that(&file2).exists();
     }
@@ -71,7 +414,7 @@ mod tests {
         let (working_dir, repo) = setup_repo();
         let untracked = working_dir.path().join("untracked");
         random_file(&untracked);
-        change_branch(&repo, "master").unwrap();
+        open(&repo).change_branch("master").unwrap();
         assert_that(&untracked).exists();
     }
 
@@ -82,14 +425,14 @@ mod tests {
         random_file(&ignored);
         let mut gitignore = File::create(working_dir.path().join(".gitignore")).unwrap();
         writeln!(gitignore, "untracked-ignored").unwrap();
-        change_branch(&repo, "master").unwrap();
+        open(&repo).change_branch("master").unwrap();
         assert_that(&ignored).exists();
     }
 
     #[test]
     fn test_change_branch_removes_tracked_files() {
         let (working_dir, repo) = setup_repo();
-        change_branch(&repo, "master").unwrap();
+        open(&repo).change_branch("master").unwrap();
         assert_that(&working_dir.path().join("file-3")).does_not_exist();
     }
 
@@ -103,12 +446,287 @@ mod tests {
             writeln!(file, "not-random string").unwrap();
         }
 
-        change_branch(&repo, "master").unwrap();
+        open(&repo).change_branch("master").unwrap();
 
         let mut file = File::open(&filename).unwrap();
         let mut buffer = String::new();
         file.read_to_string(&mut buffer).unwrap();
-        assert_that(&buffer).is_equal_to(&"not-random string\n".to_string());
+        assert_that(&buffer).is_equal_to("not-random string\n".to_string());
+    }
+
+    #[test]
+    fn test_find_entry_tries_branches_then_tags() {
+        let (_working_dir, repo) = setup_repo();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.tag_lightweight("v1.0", head.as_object(), false)
+            .unwrap();
+
+        let branch = find_entry(&*open(&repo), "master").unwrap();
+        assert_that(&branch.kind).is_equal_to(EntryKind::Branch);
+        let tag = find_entry(&*open(&repo), "v1.0").unwrap();
+        assert_that(&tag.kind).is_equal_to(EntryKind::Tag);
+        assert_that(&tag.head)
+            .is_some()
+            .is_equal_to(head.id().to_string());
+        let commit = find_entry(&*open(&repo), &head.id().to_string()).unwrap();
+        assert_that(&commit).is_equal_to(StackEntry::commit(head.id().to_string()));
+        assert_that(&find_entry(&*open(&repo), "no-such-thing"))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::InvalidRevision(_)));
+    }
+
+    #[test]
+    fn test_find_entry_reads_revisions() {
+        let (_working_dir, repo) = setup_repo();
+        let parent = repo.revparse_single("branch-2~1").unwrap().id();
+
+        let entry = find_entry(&*open(&repo), "branch-2~1").unwrap();
+        assert_that(&entry)
+            .is_equal_to(StackEntry::commit(parent.to_string()).with_revision("branch-2~1"));
+        let short = parent.to_string()[..7].to_string();
+        assert_that(&find_entry(&*open(&repo), &short).unwrap())
+            .is_equal_to(StackEntry::commit(parent.to_string()).with_revision(short));
+
+        // a full ref name still gives a branch entry
+        let branch = find_entry(&*open(&repo), "refs/heads/master").unwrap();
+        assert_that(&branch.kind).is_equal_to(EntryKind::Branch);
+        assert_that(&branch.name).is_equal_to("master".to_string());
+    }
+
+    #[test]
+    fn test_checkout_entry_detaches_head_at_commits() {
+        let (working_dir, repo) = setup_repo();
+        let master = repo.revparse_single("master").unwrap().id();
+        let entry = StackEntry::commit(master.to_string());
+
+        let backend = open(&repo);
+        backend.checkout_entry(&entry, false).unwrap();
+
+        assert_that(&repo.head_detached().unwrap()).is_true();
+        assert_that(&get_head_entry(&*backend).unwrap()).is_equal_to(&entry);
+        assert_that(&is_checked_out(&*backend, &entry).unwrap()).is_true();
+        let master = StackEntry::new("master");
+        assert_that(&is_checked_out(&*backend, &master).unwrap()).is_false();
+        assert_that(&working_dir.path().join("file-3")).does_not_exist();
+    }
+
+    #[test]
+    fn test_checkout_entry_writes_reflog_messages() {
+        let (_working_dir, repo) = setup_repo();
+        let last_message = |repo: &Repository| {
+            let reflog = repo.reflog("HEAD").unwrap();
+            reflog.get(0).unwrap().message().unwrap().to_string()
+        };
+
+        let backend = open(&repo);
+        backend
+            .checkout_entry(&StackEntry::new("master"), false)
+            .unwrap();
+        assert_that(&last_message(&repo))
+            .is_equal_to("checkout: moving from branch-2 to master".to_string());
+
+        let commit = repo.revparse_single("master~1").unwrap().id();
+        repo.reference("refs/tags/v1", commit, false, "tag")
+            .unwrap();
+        let tag = StackEntry::new("v1").with_kind(EntryKind::Tag);
+        backend.checkout_entry(&tag, false).unwrap();
+        assert_that(&last_message(&repo))
+            .is_equal_to("checkout: moving from master to v1".to_string());
+
+        backend
+            .checkout_entry(&StackEntry::new("branch-2"), false)
+            .unwrap();
+        assert_that(&last_message(&repo))
+            .is_equal_to(format!("checkout: moving from {} to branch-2", commit));
+    }
+
+    #[test]
+    fn test_create_branch_tracks_remote_branch() {
+        let (_working_dir, repo) = setup_repo();
+        let commit = repo.revparse_single("branch-2").unwrap().id();
+        repo.remote("origin", "https://example.com/repo.git")
+            .unwrap();
+        repo.reference("refs/remotes/origin/feature", commit, false, "fetch")
+            .unwrap();
+
+        let start = remote_branch_to_track(&*open(&repo), "feature").unwrap();
+        assert_that(&start)
+            .is_equal_to(StackEntry::new("origin/feature").with_kind(EntryKind::Remote));
+        let entry = create_branch(&*open(&repo), "feature", &start).unwrap();
+        assert_that(&entry).is_equal_to(StackEntry::new("feature").with_head(commit.to_string()));
+        let branch = repo.find_branch("feature", BranchType::Local).unwrap();
+        assert_that(&branch.upstream().unwrap().name().unwrap())
+            .is_some()
+            .is_equal_to("origin/feature");
+        let config = repo.config().unwrap();
+        assert_that(&config.get_string("branch.feature.merge").unwrap())
+            .is_equal_to("refs/heads/feature".to_string());
+    }
+
+    #[test]
+    fn test_remote_branch_to_track_refuses_ambiguous_names() {
+        let (_working_dir, repo) = setup_repo();
+        let commit = repo.revparse_single("branch-2").unwrap().id();
+        for remote in &["origin", "upstream"] {
+            repo.remote(remote, "https://example.com/repo.git").unwrap();
+            let refname = format!("refs/remotes/{}/feature", remote);
+            repo.reference(&refname, commit, false, "fetch").unwrap();
+        }
+
+        assert_that(&remote_branch_to_track(&*open(&repo), "feature"))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::AmbiguousRemoteBranch(_, _)));
+        assert_that(&repo.find_branch("feature", BranchType::Local).is_err()).is_true();
+    }
+
+    #[test]
+    fn test_temp_branch_name_skips_existing_branches() {
+        let (_working_dir, repo) = setup_repo();
+        let start = StackEntry::new("origin/feature~1").with_kind(EntryKind::Remote);
+        assert_that(&temp_branch_name(&*open(&repo), &start).unwrap())
+            .is_equal_to("tmp/origin/feature-1".to_string());
+
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("tmp/branch-2", &commit, false).unwrap();
+        let start = StackEntry::new("branch-2");
+        assert_that(&temp_branch_name(&*open(&repo), &start).unwrap())
+            .is_equal_to("tmp/branch-2-2".to_string());
+    }
+
+    #[test]
+    fn test_is_reachable_elsewhere() {
+        let (working_dir, repo) = setup_repo();
+        let master = repo
+            .revparse_single("master~1")
+            .unwrap()
+            .peel_to_commit()
+            .unwrap();
+        repo.branch("tmp/old", &master, false).unwrap();
+        assert_that(&is_reachable_elsewhere(&*open(&repo), "tmp/old").unwrap()).is_true();
+
+        // a commit that's only on this branch
+        checkout_new_branch(&repo, &master, "tmp/new");
+        commit_random_file(
+            working_dir.path(),
+            &repo,
+            &master.author(),
+            "file-4",
+            "commit 4",
+        )
+        .unwrap();
+        open(&repo).change_branch("master").unwrap();
+        assert_that(&is_reachable_elsewhere(&*open(&repo), "tmp/new").unwrap()).is_false();
+    }
+
+    #[test]
+    fn test_check_repo_state_refuses_operations_in_progress() {
+        let (_working_dir, repo) = setup_repo();
+        assert_that(&check_repo_state(&*open(&repo))).is_ok();
+
+        let head = repo.head().unwrap().target().unwrap();
+        File::create(repo.path().join("MERGE_HEAD"))
+            .unwrap()
+            .write_all(format!("{}\n", head).as_bytes())
+            .unwrap();
+        assert_that(&check_repo_state(&*open(&repo)))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::MergeInProgress));
+        repo.cleanup_state().unwrap();
+
+        File::create(repo.path().join("BISECT_LOG")).unwrap();
+        assert_that(&check_repo_state(&*open(&repo)))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::BisectInProgress));
+    }
+
+    #[test]
+    fn test_check_repo_state_refuses_unborn_and_bare_repositories() {
+        let working_dir = tempdir().unwrap();
+        let repo = Repository::init(working_dir.path()).unwrap();
+        assert_that(&check_repo_state(&*open(&repo)))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::UnbornHead));
+
+        let bare_dir = tempdir().unwrap();
+        let bare = Repository::init_bare(bare_dir.path()).unwrap();
+        assert_that(&check_repo_state(&*open(&bare)))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::BareRepository));
+    }
+
+    #[test]
+    fn test_change_branch_lists_conflicting_files() {
+        let (working_dir, repo) = setup_repo();
+        let filename = working_dir.path().join("file-3");
+        {
+            let mut file = File::create(&filename).unwrap();
+            writeln!(file, "not-random string").unwrap();
+        }
+
+        let result = open(&repo).change_branch("master");
+        assert_that(&result).is_err().matches(|v| match v {
+            BranchStackError::CheckoutConflicts(conflicts) => {
+                conflicts
+                    == &vec![CheckoutConflict {
+                        path: PathBuf::from("file-3"),
+                        reason: ConflictReason::Deleted,
+                    }]
+            }
+            _ => false,
+        });
+        assert_that(&filename).exists();
+    }
+
+    #[test]
+    fn test_force_checkout_entry_discards_changes() {
+        let (working_dir, repo) = setup_repo();
+        let filename = working_dir.path().join("file-1");
+        {
+            let mut file = File::create(&filename).unwrap();
+            writeln!(file, "not-random string").unwrap();
+        }
+
+        open(&repo)
+            .checkout_entry(&StackEntry::new("master"), true)
+            .unwrap();
+
+        let mut buffer = String::new();
+        File::open(&filename)
+            .unwrap()
+            .read_to_string(&mut buffer)
+            .unwrap();
+        assert_that(&buffer).is_not_equal_to("not-random string\n".to_string());
+    }
+
+    #[test]
+    fn test_ref_file_keeps_history() {
+        let (_working_dir, repo) = setup_repo();
+        let backend = open(&repo);
+        let refname = "refs/branch-stack/test";
+        assert_that(&backend.read_ref_file(refname, "stack.json").unwrap()).is_none();
+
+        let first = backend
+            .commit_ref_file(refname, "stack.json", b"one", "first")
+            .unwrap();
+        let second = backend
+            .commit_ref_file(refname, "stack.json", b"two", "second")
+            .unwrap();
+        assert_that(&backend.read_ref_file(refname, "stack.json").unwrap())
+            .is_some()
+            .is_equal_to(b"two".to_vec());
+        let commit = repo.find_commit(Oid::from_str(&second).unwrap()).unwrap();
+        assert_that(&commit.parent_id(0).unwrap().to_string()).is_equal_to(first);
+
+        // saving the same contents doesn't add a commit
+        let third = backend
+            .commit_ref_file(refname, "stack.json", b"two", "third")
+            .unwrap();
+        assert_that(&third).is_equal_to(second);
+    }
+
+    /// Open `repo` with the default backend.
+    fn open(repo: &Repository) -> Box<dyn GitBackend> {
+        open_backend(repo.path(), BackendKind::default()).unwrap()
     }
 
     fn setup_repo() -> (TempDir, Repository) {
@@ -157,15 +775,15 @@ mod tests {
 
         random_file(dirname.join(filename));
 
-        index.add_path(&Path::new(filename)).unwrap();
+        index.add_path(Path::new(filename)).unwrap();
         let index_oid = index.write_tree().unwrap();
         let index_tree = repo.find_tree(index_oid).unwrap();
 
         let commit_id = repo
             .commit(
                 Some("HEAD"),
-                &author,
-                &author,
+                author,
+                author,
                 commit_message,
                 &index_tree,
                 &[&head_commit],
@@ -182,7 +800,7 @@ mod tests {
     }
 
     fn checkout_new_branch<'a>(repo: &Repository, commit: &Commit<'a>, branch_name: &str) {
-        repo.branch(branch_name, &commit, false).unwrap();
+        repo.branch(branch_name, commit, false).unwrap();
         let refname = format!("refs/heads/{}", branch_name);
         repo.set_head(&refname).unwrap();
 
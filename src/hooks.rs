@@ -0,0 +1,143 @@
+/// # Hooks
+///
+/// libgit2 and gitoxide don't run git's hooks, so the ones that go with switching
+/// branches are run here. Hooks are looked for in `core.hooksPath` if it's
+/// set, and in the `hooks` directory of the common git directory if it
+/// isn't. A relative `core.hooksPath` is taken from the top of the working
+/// tree, the same as git does.
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::backend::GitBackend;
+use crate::errors::{BranchStackError, Result};
+use crate::git::common_dir;
+
+/// The commit id git passes to hooks when there's no commit.
+const ZERO_ID: &str = "0000000000000000000000000000000000000000";
+
+/// The directory hooks are run from. A `core.hooksPath` starting with `~/`
+/// is taken from the home directory, the way git reads config paths.
+fn hooks_dir(repo: &dyn GitBackend) -> Result<PathBuf> {
+    let path = match repo.config_string("core.hooksPath")? {
+        Some(path) => path,
+        None => return Ok(common_dir(repo)?.join("hooks")),
+    };
+    let path = match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    };
+    if path.is_relative() {
+        let base = repo.workdir().unwrap_or_else(|| repo.git_dir());
+        Ok(base.join(path))
+    } else {
+        Ok(path)
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Run the hook `name` with `args` from the top of the working tree. If
+/// there's no such hook, or it isn't executable, nothing happens. If it
+/// can't be started or exits with an error, this returns
+/// `Err(BranchStackError::HookFailed)`.
+fn run_hook(repo: &dyn GitBackend, name: &str, args: &[&str]) -> Result<()> {
+    let hook = hooks_dir(repo)?.join(name);
+    if !is_executable(&hook) {
+        return Ok(());
+    }
+
+    let workdir = repo.workdir().unwrap_or_else(|| repo.git_dir());
+    let status = Command::new(&hook)
+        .args(args)
+        .current_dir(workdir)
+        .status()
+        .map_err(|err| BranchStackError::HookFailed(name.to_string(), err.to_string()))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(BranchStackError::HookFailed(
+            name.to_string(),
+            status.to_string(),
+        ))
+    }
+}
+
+/// Run the `post-checkout` hook for a switch from the commit `old` to the
+/// commit `new`. Like `git checkout`, this passes the flag that says the
+/// whole branch changed, not just some files.
+pub fn run_post_checkout(repo: &dyn GitBackend, old: Option<&str>, new: &str) -> Result<()> {
+    let old = old.unwrap_or(ZERO_ID);
+    run_hook(repo, "post-checkout", &[old, new, "1"])
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs::{create_dir_all, read_to_string, set_permissions, write, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    use git2::Repository;
+    use spectral::prelude::*;
+    use tempfile::tempdir;
+
+    use super::{run_post_checkout, ZERO_ID};
+    use crate::backend::{open_backend, BackendKind};
+    use crate::errors::BranchStackError;
+
+    fn write_hook(path: &Path, script: &str) {
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, script).unwrap();
+        set_permissions(path, Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_post_checkout_gets_git_arguments() {
+        let working_dir = tempdir().unwrap();
+        let repo = Repository::init(working_dir.path()).unwrap();
+        write_hook(
+            &repo.path().join("hooks/post-checkout"),
+            "#!/bin/sh\necho \"$@\" > hook-args\n",
+        );
+
+        let backend = open_backend(working_dir.path(), BackendKind::default()).unwrap();
+        let new = "0123456789abcdef0123456789abcdef01234567";
+        run_post_checkout(&*backend, None, new).unwrap();
+
+        let args = read_to_string(working_dir.path().join("hook-args")).unwrap();
+        assert_that(&args).is_equal_to(format!("{} {} 1\n", ZERO_ID, new));
+    }
+
+    #[test]
+    fn test_post_checkout_respects_hooks_path() {
+        let working_dir = tempdir().unwrap();
+        let repo = Repository::init(working_dir.path()).unwrap();
+        repo.config()
+            .unwrap()
+            .set_str("core.hooksPath", "my-hooks")
+            .unwrap();
+        write_hook(
+            &working_dir.path().join("my-hooks/post-checkout"),
+            "#!/bin/sh\nexit 3\n",
+        );
+
+        let backend = open_backend(working_dir.path(), BackendKind::default()).unwrap();
+        let result = run_post_checkout(&*backend, None, ZERO_ID);
+        assert_that(&result).is_err().matches(|err| match err {
+            BranchStackError::HookFailed(name, _) => name == "post-checkout",
+            _ => false,
+        });
+    }
+}
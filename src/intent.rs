@@ -0,0 +1,354 @@
+/// # Intent Records
+///
+/// Changing branches is a two-step process: first the working tree is
+/// checked out, and then the stack is saved. If the process dies between
+/// those two steps, the stack and HEAD no longer agree.
+///
+/// To recover from that, an intent record (`BRANCH_STACK.intent` in the git
+/// directory) is written before anything is checked out. It holds the stack
+/// and branch from before the operation and what they should be afterward.
+/// Once the stack is saved, the record is removed. If a later invocation
+/// finds one lying around, the operation was interrupted, and it can be
+/// finished with `continue` or rolled back with `abort`, much like an
+/// interrupted `git rebase`. A process that was killed also leaves the
+/// stack's lock behind, so those two take it over.
+///
+/// Any stash recorded on the entry that's finally checked out is
+/// re-applied, the same as when the operation isn't interrupted.
+use std::fs::{read_to_string, remove_file};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::GitBackend;
+use crate::errors::{BranchStackError, Result};
+use crate::git::{
+    check_repo_state, find_other_worktree, get_head_entry, is_checked_out, CheckoutConflict,
+};
+use crate::hooks::run_post_checkout;
+use crate::journal::{Journal, Operation, Record};
+use crate::lock::write_atomic;
+use crate::stack::{entry_or_name, FileStack, Stack, StackEntry, Storage};
+use crate::stash::{is_dirty, restore_stash, stash_changes, DirtyPolicy};
+use crate::submodules::update_submodules;
+
+/// An operation that has been started but not finished.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Intent {
+    /// The stack file the operation changes.
+    pub stack_file: PathBuf,
+    /// Where that stack's entries are persisted.
+    #[serde(default)]
+    pub storage: Storage,
+    /// A description of the action, such as `push master`.
+    pub action: String,
+    /// The stack, top down, before the operation.
+    pub before: Vec<StackEntry>,
+    /// The stack, top down, after the operation.
+    pub after: Vec<StackEntry>,
+    /// What was checked out before the operation.
+    #[serde(deserialize_with = "entry_or_name")]
+    pub head_before: StackEntry,
+    /// What to check out.
+    #[serde(deserialize_with = "entry_or_name")]
+    pub target: StackEntry,
+    /// What to add to the journal when the operation is finished.
+    pub record: Record,
+}
+
+impl Intent {
+    /// The intent file for a repository.
+    pub fn path(repo: &dyn GitBackend) -> PathBuf {
+        repo.git_dir().join("BRANCH_STACK.intent")
+    }
+
+    /// Read the unfinished intent for the repository, if there is one.
+    pub fn read(repo: &dyn GitBackend) -> Result<Option<Intent>> {
+        let path = Intent::path(repo);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let buffer = read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&buffer)?))
+    }
+
+    /// Fail with `BranchStackError::InterruptedOperation` if there's an
+    /// unfinished intent for the repository.
+    pub fn check(repo: &dyn GitBackend) -> Result<()> {
+        match Intent::read(repo)? {
+            Some(intent) => Err(BranchStackError::InterruptedOperation(intent.action)),
+            None => Ok(()),
+        }
+    }
+
+    fn write(&self, repo: &dyn GitBackend) -> Result<()> {
+        let buffer = serde_json::to_vec_pretty(self)?;
+        write_atomic(Intent::path(repo), &buffer)
+    }
+
+    fn clear(repo: &dyn GitBackend) -> Result<()> {
+        let path = Intent::path(repo);
+        if path.exists() {
+            remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for how `switch` changes branches.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SwitchOptions {
+    /// What to do with uncommitted changes.
+    pub dirty: DirtyPolicy,
+    /// Whether to offer to throw away local changes that are in the way of
+    /// the checkout.
+    pub force: bool,
+    /// Whether to check out a branch even if another worktree already has
+    /// it checked out.
+    pub ignore_other_worktrees: bool,
+    /// Whether to update submodules to the commits recorded on the branch
+    /// that's checked out.
+    pub recurse_submodules: bool,
+}
+
+/// Change the stack and the current branch as a single operation.
+///
+/// `f` is given the stack and an entry for what's checked out now, stamped
+/// with the current time. It makes its changes to the stack and returns
+/// the entry to check out. The steps are:
+///
+/// 1. Check that the repository is in a state where branches can be
+///    switched.
+/// 2. Deal with uncommitted changes according to `options.dirty`. If
+///    they're stashed, the stash is recorded on the entry given to `f`.
+/// 3. Refuse a branch that's checked out in another worktree, unless
+///    `options.ignore_other_worktrees` is set.
+/// 4. Write an intent record.
+/// 5. Check out the entry with `repo`'s backend. If that would overwrite local changes and `options.force` is set,
+///    the user is asked whether to discard them.
+/// 6. Save the stack, append the record built by `to_record` to the
+///    journal, and remove the intent.
+/// 7. Re-apply any stash recorded on the entry that was checked out.
+/// 8. Update submodules if `options.recurse_submodules` is set, and run
+///    the `post-checkout` hook if the backend didn't. This happens even if
+///    the stash couldn't be re-applied; that error is returned afterward.
+///
+/// If `f` or the checkout fails, the stack is restored, any stash that was
+/// just made is re-applied, and nothing is written. If saving the stack
+/// fails, the intent is left behind so the operation can be finished or
+/// rolled back later. Stacks without a `location` can't be opened again to
+/// do that, so no intent is written for them.
+pub fn switch<S, F, R>(
+    repo: &dyn GitBackend,
+    stack: &mut S,
+    journal: &Journal,
+    options: &SwitchOptions,
+    action: &str,
+    to_record: R,
+    f: F,
+) -> Result<StackEntry>
+where
+    S: Stack,
+    F: FnOnce(&mut S, StackEntry) -> Result<StackEntry>,
+    R: FnOnce(Operation) -> Record,
+{
+    check_repo_state(repo)?;
+    let before: Vec<StackEntry> = stack.iter().cloned().collect();
+    let mut head_before = get_head_entry(repo)?;
+    let recoverable = stack.location().is_some();
+
+    if options.dirty != DirtyPolicy::Carry && is_dirty(repo)? {
+        if options.dirty == DirtyPolicy::Abort {
+            return Err(BranchStackError::DirtyWorkingTree);
+        }
+        head_before.stash = Some(stash_changes(repo, &head_before)?);
+    }
+    let stash = head_before.stash.clone();
+    let current = head_before.clone().pushed_now();
+
+    let result = stack.transaction(|stack| {
+        let target = f(stack, current)?;
+        if target.is_branch() && !options.ignore_other_worktrees {
+            if let Some(path) = find_other_worktree(repo, target.name_bytes())? {
+                return Err(BranchStackError::CheckedOutElsewhere(
+                    target.name.clone(),
+                    path,
+                ));
+            }
+        }
+        let after: Vec<StackEntry> = stack.iter().cloned().collect();
+        let operation = journal.operation(
+            action.to_string(),
+            before.clone(),
+            after.clone(),
+            head_before.clone(),
+            target.clone(),
+        )?;
+        let location = stack.location();
+        let intent = Intent {
+            stack_file: location
+                .map(|(path, _)| path.to_path_buf())
+                .unwrap_or_default(),
+            storage: location
+                .map(|(_, storage)| storage.clone())
+                .unwrap_or_default(),
+            action: action.to_string(),
+            before,
+            after,
+            head_before: head_before.clone(),
+            target,
+            record: to_record(operation),
+        };
+
+        if recoverable {
+            intent.write(repo)?;
+        }
+        let checked_out = match repo.checkout_entry(&intent.target, false) {
+            Err(BranchStackError::CheckoutConflicts(ref conflicts))
+                if options.force && confirm_discard(conflicts)? =>
+            {
+                repo.checkout_entry(&intent.target, true)
+            }
+            checked_out => checked_out,
+        };
+        if let Err(err) = checked_out {
+            if recoverable {
+                Intent::clear(repo)?;
+            }
+            return Err(err);
+        }
+        Ok(intent)
+    });
+    let intent = match result {
+        Ok(intent) => intent,
+        Err(err) => {
+            if let Some(ref stash) = stash {
+                if is_checked_out(repo, &head_before).unwrap_or(false) {
+                    restore_stash(repo, &head_before, stash)?;
+                }
+            }
+            return Err(err);
+        }
+    };
+
+    journal.append(&intent.record)?;
+    if recoverable {
+        Intent::clear(repo)?;
+    }
+    if let Some(ref stash) = stash {
+        if !intent
+            .after
+            .iter()
+            .any(|entry| entry.stash.as_ref() == Some(stash))
+        {
+            eprintln!(
+                "stashed the changes on {} as {}; it's not on the stack, so \
+                 they won't be re-applied automatically",
+                head_before, stash
+            );
+        }
+    }
+    // The branch is switched even if the stash can't be re-applied, so the
+    // rest still happens before that's reported.
+    let restored = restore_entry_stash(repo, &intent.target);
+    if options.recurse_submodules {
+        if let Err(err) = update_submodules(repo) {
+            eprintln!("warning: {}", err);
+        }
+    }
+    if !repo.runs_hooks() {
+        post_checkout(repo, &head_before);
+    }
+    restored?;
+    Ok(intent.target)
+}
+
+/// List the paths that are in the way of a checkout and ask on the terminal
+/// whether to throw away the changes to them.
+fn confirm_discard(conflicts: &[CheckoutConflict]) -> Result<bool> {
+    eprintln!("switching branches would discard your local changes to:");
+    for conflict in conflicts {
+        eprintln!("    {}", conflict);
+    }
+    eprint!("Discard these changes? [y/N] ");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+/// Run the `post-checkout` hook for a switch away from `head_before` to
+/// whatever HEAD is now. The stack has already been saved by the time this
+/// runs, so if the hook fails, that's only reported.
+fn post_checkout(repo: &dyn GitBackend, head_before: &StackEntry) {
+    let old = head_before.head.as_deref();
+    let result = repo
+        .head_commit()
+        .and_then(|new| run_post_checkout(repo, old, &new));
+    if let Err(err) = result {
+        eprintln!("warning: {}", err);
+    }
+}
+
+/// Re-apply the stash recorded on `entry`, if there is one.
+fn restore_entry_stash(repo: &dyn GitBackend, entry: &StackEntry) -> Result<()> {
+    match entry.stash {
+        Some(ref stash) => restore_stash(repo, entry, stash),
+        None => Ok(()),
+    }
+}
+
+/// Finish an interrupted operation: check out its target, save the stack it
+/// was writing, and record it in the journal.
+pub fn continue_intent(repo: &dyn GitBackend) -> Result<StackEntry> {
+    let intent = Intent::read(repo)?.ok_or(BranchStackError::NoInterruptedOperation)?;
+    check_repo_state(repo)?;
+    let mut stack = FileStack::recover(&intent.stack_file, intent.storage.clone())?;
+    let journal = Journal::for_stack(&intent.stack_file);
+
+    // The operation may have been interrupted after the checkout, in which
+    // case the target only needs to be recorded.
+    let needs_checkout = !is_checked_out(repo, &intent.target).unwrap_or(false);
+    if needs_checkout {
+        repo.checkout_entry(&intent.target, false)?;
+    }
+    stack.replace(intent.after.iter().cloned());
+    stack.save()?;
+    // If it was interrupted just before the intent was removed, it's
+    // already in the journal.
+    if !journal.ends_with(&intent.record)? {
+        journal.append(&intent.record)?;
+    }
+    Intent::clear(repo)?;
+    let restored = restore_entry_stash(repo, &intent.target);
+    if needs_checkout && !repo.runs_hooks() {
+        post_checkout(repo, &intent.head_before);
+    }
+    restored?;
+    Ok(intent.target)
+}
+
+/// Roll back an interrupted operation: check out what was checked out
+/// before it started and put back the stack it was changing.
+pub fn abort_intent(repo: &dyn GitBackend) -> Result<StackEntry> {
+    let intent = Intent::read(repo)?.ok_or(BranchStackError::NoInterruptedOperation)?;
+    check_repo_state(repo)?;
+    let mut stack = FileStack::recover(&intent.stack_file, intent.storage.clone())?;
+
+    let needs_checkout = !is_checked_out(repo, &intent.head_before).unwrap_or(false);
+    if needs_checkout {
+        repo.checkout_entry(&intent.head_before, false)?;
+    }
+    stack.replace(intent.before.iter().cloned());
+    stack.save()?;
+    Intent::clear(repo)?;
+    let restored = restore_entry_stash(repo, &intent.head_before);
+    if needs_checkout && !repo.runs_hooks() {
+        post_checkout(repo, &intent.target);
+    }
+    restored?;
+    Ok(intent.head_before)
+}
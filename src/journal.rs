@@ -0,0 +1,307 @@
+/// # Operation Journal
+///
+/// Every action that changes the stack is recorded in an append-only
+/// journal next to the stack file (`BRANCH_STACK.journal`). Each line is a
+/// JSON record. An operation record holds the stack and the current branch
+/// from before and after the action ran. Undoing or redoing an operation
+/// appends another record that refers back to it, so the journal is never
+/// rewritten.
+///
+/// Replaying the journal gives two lists: the operations that are in effect
+/// and can be undone, and the ones that have been undone and can be redone.
+/// Recording a new operation clears the redo list, the same way an editor's
+/// undo history works.
+///
+/// A journal can also be kept in memory, to go along with a `MemoryStack`.
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{BranchStackError, Result};
+use crate::lock::sibling;
+use crate::stack::{entry_or_name, StackEntry};
+
+/// A single action applied to the stack.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Operation {
+    /// A number identifying this operation in the journal.
+    pub id: u64,
+    /// A description of the action, such as `push master`.
+    pub action: String,
+    /// When the action ran, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    /// The stack, top down, before the action.
+    pub before: Vec<StackEntry>,
+    /// The stack, top down, after the action.
+    pub after: Vec<StackEntry>,
+    /// What was checked out before the action.
+    #[serde(deserialize_with = "entry_or_name")]
+    pub head_before: StackEntry,
+    /// What was checked out after the action.
+    #[serde(deserialize_with = "entry_or_name")]
+    pub head_after: StackEntry,
+}
+
+/// One line in the journal.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Record {
+    /// An action was applied.
+    Apply(Operation),
+    /// The operation with this id was undone.
+    Undo { id: u64 },
+    /// The operation with this id was redone.
+    Redo { id: u64 },
+}
+
+/// The journal file for a stack.
+#[derive(Debug)]
+pub struct Journal {
+    /// The journal file. If this is `None`, the records are only kept in
+    /// `memory`.
+    filename: Option<PathBuf>,
+    memory: RefCell<Vec<Record>>,
+}
+
+/// The state of the journal after replaying all of its records.
+#[derive(Debug, Default)]
+struct History {
+    next_id: u64,
+    done: Vec<Operation>,
+    undone: Vec<Operation>,
+}
+
+impl Journal {
+    /// The journal that goes with the stack stored in `stack_file`.
+    pub fn for_stack<P: AsRef<Path>>(stack_file: P) -> Journal {
+        Journal {
+            filename: Some(sibling(stack_file.as_ref(), ".journal")),
+            memory: RefCell::default(),
+        }
+    }
+
+    /// A journal that's never written to disc.
+    pub fn in_memory() -> Journal {
+        Journal {
+            filename: None,
+            memory: RefCell::default(),
+        }
+    }
+
+    /// Build the operation record for an action. This doesn't write
+    /// anything; pass the result to `append` once the action is done.
+    pub fn operation(
+        &self,
+        action: String,
+        before: Vec<StackEntry>,
+        after: Vec<StackEntry>,
+        head_before: StackEntry,
+        head_after: StackEntry,
+    ) -> Result<Operation> {
+        let history = self.history()?;
+        Ok(Operation {
+            id: history.next_id,
+            action,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|duration| duration.as_secs()),
+            before,
+            after,
+            head_before,
+            head_after,
+        })
+    }
+
+    /// The most recent operation that is still in effect.
+    pub fn last_done(&self) -> Result<Option<Operation>> {
+        Ok(self.history()?.done.pop())
+    }
+
+    /// The most recently undone operation.
+    pub fn last_undone(&self) -> Result<Option<Operation>> {
+        Ok(self.history()?.undone.pop())
+    }
+
+    /// Read all of the records in the journal, oldest first.
+    pub fn records(&self) -> Result<Vec<Record>> {
+        let filename = match self.filename {
+            Some(ref filename) => filename,
+            None => return Ok(self.memory.borrow().clone()),
+        };
+        if !filename.exists() {
+            return Ok(Vec::new());
+        }
+
+        let file = File::open(filename)?;
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records
+                .push(serde_json::from_str(&line).map_err(BranchStackError::JournalFormatError)?);
+        }
+        Ok(records)
+    }
+
+    /// Whether `record` is the last one in the journal.
+    pub fn ends_with(&self, record: &Record) -> Result<bool> {
+        Ok(self.records()?.last() == Some(record))
+    }
+
+    fn history(&self) -> Result<History> {
+        let mut history = History::default();
+        for record in self.records()? {
+            match record {
+                Record::Apply(operation) => {
+                    history.next_id = history.next_id.max(operation.id + 1);
+                    history.done.push(operation);
+                    history.undone.clear();
+                }
+                Record::Undo { id } => {
+                    if let Some(i) = history.done.iter().rposition(|op| op.id == id) {
+                        let operation = history.done.remove(i);
+                        history.undone.push(operation);
+                    }
+                }
+                Record::Redo { id } => {
+                    if let Some(i) = history.undone.iter().rposition(|op| op.id == id) {
+                        let operation = history.undone.remove(i);
+                        history.done.push(operation);
+                    }
+                }
+            }
+        }
+        Ok(history)
+    }
+
+    /// Add a record to the end of the journal.
+    pub fn append(&self, record: &Record) -> Result<()> {
+        let filename = match self.filename {
+            Some(ref filename) => filename,
+            None => {
+                self.memory.borrow_mut().push(record.clone());
+                return Ok(());
+            }
+        };
+        let mut line =
+            serde_json::to_string(record).map_err(BranchStackError::JournalFormatError)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filename)?;
+        file.write_all(line.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use spectral::prelude::*;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup_journal() -> (TempDir, Journal) {
+        let basedir = tempdir().unwrap();
+        let journal = Journal::for_stack(basedir.path().join("BRANCH_STACK"));
+        (basedir, journal)
+    }
+
+    fn apply(journal: &Journal, action: &str) -> Operation {
+        let operation = journal
+            .operation(
+                action.to_string(),
+                vec![],
+                vec![StackEntry::new("master")],
+                StackEntry::new("master"),
+                StackEntry::new(action),
+            )
+            .unwrap();
+        journal.append(&Record::Apply(operation.clone())).unwrap();
+        operation
+    }
+
+    #[test]
+    fn empty_journal_has_nothing_to_undo_or_redo() {
+        let (_basedir, journal) = setup_journal();
+        assert_that(&journal.last_done().unwrap()).is_none();
+        assert_that(&journal.last_undone().unwrap()).is_none();
+    }
+
+    #[test]
+    fn operations_get_increasing_ids() {
+        let (_basedir, journal) = setup_journal();
+        let first = apply(&journal, "first");
+        let second = apply(&journal, "second");
+        assert_that(&first.id).is_equal_to(0);
+        assert_that(&second.id).is_equal_to(1);
+    }
+
+    #[test]
+    fn undo_and_redo_move_operations_between_lists() {
+        let (_basedir, journal) = setup_journal();
+        apply(&journal, "first");
+        let second = apply(&journal, "second");
+
+        journal.append(&Record::Undo { id: second.id }).unwrap();
+        assert_that(&journal.last_done().unwrap().map(|op| op.action))
+            .is_some()
+            .is_equal_to("first".to_string());
+        assert_that(&journal.last_undone().unwrap())
+            .is_some()
+            .is_equal_to(&second);
+
+        journal.append(&Record::Redo { id: second.id }).unwrap();
+        assert_that(&journal.last_done().unwrap())
+            .is_some()
+            .is_equal_to(&second);
+        assert_that(&journal.last_undone().unwrap()).is_none();
+    }
+
+    #[test]
+    fn in_memory_journal_keeps_records() {
+        let journal = Journal::in_memory();
+        let first = apply(&journal, "first");
+        journal.append(&Record::Undo { id: first.id }).unwrap();
+        assert_that(&journal.last_done().unwrap()).is_none();
+        assert_that(&journal.last_undone().unwrap())
+            .is_some()
+            .is_equal_to(&first);
+    }
+
+    #[test]
+    fn old_records_name_the_branch() {
+        let line = r#"{"kind": "apply", "id": 0, "action": "push master",
+            "before": [], "after": [{"name": "topic"}],
+            "head_before": "topic", "head_after": "master"}"#;
+        let record: Record = serde_json::from_str(line).unwrap();
+        match record {
+            Record::Apply(operation) => {
+                assert_that(&operation.head_before).is_equal_to(StackEntry::new("topic"));
+                assert_that(&operation.head_after).is_equal_to(StackEntry::new("master"));
+            }
+            _ => panic!("expected an apply record"),
+        }
+    }
+
+    #[test]
+    fn new_operations_clear_redo_list() {
+        let (_basedir, journal) = setup_journal();
+        let first = apply(&journal, "first");
+        journal.append(&Record::Undo { id: first.id }).unwrap();
+        apply(&journal, "second");
+        assert_that(&journal.last_undone().unwrap()).is_none();
+        assert_that(&journal.records().unwrap().len()).is_equal_to(3);
+    }
+}
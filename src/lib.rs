@@ -9,6 +9,14 @@
 /// Popping a branch removes the current branch from the stack and checks
 /// out the next branch down.
 pub mod actions;
+pub mod backend;
 pub mod errors;
 pub mod git;
+pub mod hooks;
+pub mod intent;
+pub mod journal;
+pub mod lock;
 pub mod stack;
+pub mod stacks;
+pub mod stash;
+pub mod submodules;
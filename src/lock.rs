@@ -0,0 +1,145 @@
+/// # Lock Files
+///
+/// This guards files in the git directory the same way that git itself
+/// does. Before changing `FILE`, a process creates `FILE.lock` exclusively.
+/// If that file already exists, someone else is working on `FILE`, and we
+/// back off. The lock is released by removing the lock file.
+///
+/// A process that's killed leaves its lock file behind. There's no telling
+/// that apart from a lock that's still held, so it's only taken over when
+/// something else shows that its owner died, such as an interrupted
+/// operation's intent record.
+///
+/// Writes go to a temporary file next to the target, which is then renamed
+/// over it, so readers never see a half-written file.
+use std::ffi::OsString;
+use std::fs::{remove_file, rename, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::errors::{BranchStackError, Result};
+
+/// An exclusive lock on a file. The lock is held until this is dropped.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Take the lock for `target`. If another process holds it, this
+    /// returns `Err(BranchStackError::Locked)`.
+    pub fn acquire<P: AsRef<Path>>(target: P) -> Result<LockFile> {
+        let path = sibling(target.as_ref(), ".lock");
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(LockFile { path }),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                Err(BranchStackError::Locked(path))
+            }
+            Err(err) => Err(BranchStackError::from(err)),
+        }
+    }
+
+    /// Take the lock for `target`, even if a lock file is already there.
+    /// This is only for locks that were left behind by a process that died
+    /// while holding them; it's up to the caller to know that's the case.
+    pub fn take_over<P: AsRef<Path>>(target: P) -> Result<LockFile> {
+        let path = sibling(target.as_ref(), ".lock");
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        Ok(LockFile { path })
+    }
+
+    /// The path to the lock file itself.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+/// Replace the contents of `target` with `contents`. The data is written
+/// to a temporary file first and then renamed into place.
+pub fn write_atomic<P: AsRef<Path>>(target: P, contents: &[u8]) -> Result<()> {
+    let target = target.as_ref();
+    let temp_path = sibling(target, ".tmp");
+    {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    rename(&temp_path, target).map_err(|err| {
+        let _ = remove_file(&temp_path);
+        BranchStackError::from(err)
+    })
+}
+
+/// Create a path next to `path` with `suffix` added to the file name.
+pub fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().map(OsString::from).unwrap_or_default();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::read_to_string;
+
+    use spectral::prelude::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn acquire_creates_and_drop_removes_the_lock_file() {
+        let basedir = tempdir().unwrap();
+        let target = basedir.path().join("STACK");
+        let lock_path = {
+            let lock = LockFile::acquire(&target).unwrap();
+            assert_that(&lock.path()).exists();
+            lock.path().to_path_buf()
+        };
+        assert_that(&lock_path).does_not_exist();
+    }
+
+    #[test]
+    fn acquire_fails_while_the_lock_is_held() {
+        let basedir = tempdir().unwrap();
+        let target = basedir.path().join("STACK");
+        let _lock = LockFile::acquire(&target).unwrap();
+        assert_that(&LockFile::acquire(&target))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::Locked(_)));
+    }
+
+    #[test]
+    fn take_over_succeeds_while_the_lock_is_held() {
+        let basedir = tempdir().unwrap();
+        let target = basedir.path().join("STACK");
+        let lock = LockFile::acquire(&target).unwrap();
+        std::mem::forget(lock);
+
+        let lock_path = {
+            let lock = LockFile::take_over(&target).unwrap();
+            assert_that(&lock.path()).exists();
+            lock.path().to_path_buf()
+        };
+        assert_that(&lock_path).does_not_exist();
+    }
+
+    #[test]
+    fn write_atomic_replaces_contents_and_cleans_up() {
+        let basedir = tempdir().unwrap();
+        let target = basedir.path().join("STACK");
+        write_atomic(&target, b"first").unwrap();
+        write_atomic(&target, b"second").unwrap();
+        assert_that(&read_to_string(&target).unwrap()).is_equal_to("second".to_string());
+        assert_that(&sibling(&target, ".tmp")).does_not_exist();
+    }
+}
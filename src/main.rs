@@ -1,65 +1,370 @@
+use std::ffi::OsStr;
+use std::process;
+
 use clap::{
-    app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches,
-    SubCommand,
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, AppSettings, Arg,
+    ArgMatches, SubCommand,
 };
 
 use git_branch_stack::actions::rotate::parse_rotation;
-use git_branch_stack::actions::{invoke_action, Action};
+use git_branch_stack::actions::stacks::StacksAction;
+use git_branch_stack::actions::{invoke_action, Action, Options};
+use git_branch_stack::backend::BackendKind;
 use git_branch_stack::errors::{BranchStackError, Result};
+use git_branch_stack::stacks::StackScope;
+use git_branch_stack::stash::DirtyPolicy;
 
-/// The main entry-point. Not really interesting.
-fn main() -> Result<()> {
-    let action = parse_args()?;
-    invoke_action(action)
+/// The main entry-point. Not really interesting, except that errors are
+/// printed with their messages, which say how to get out of trouble, and
+/// then the process exits with a failure status.
+fn main() {
+    let result = parse_args().and_then(|(action, options)| invoke_action(action, &options));
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
 }
 
-/// Parse all of the command-line options into an `Action` that can be run.
-fn parse_args() -> Result<Action> {
+/// Parse all of the command-line options into an `Action` that can be run,
+/// along with the `Options` that apply to it.
+fn parse_args() -> Result<(Action, Options)> {
     let arg_matches = app_from_crate!()
         .about("Maintain a stack of branches for easy navigation.")
+        .arg(
+            Arg::with_name("stack")
+                .long("stack")
+                .help("The name of the stack to use instead of the current one.")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scope")
+                .long("scope")
+                .help(
+                    "Where to keep stacks when using linked worktrees: in \
+                     this worktree's own git directory, or shared by all \
+                     worktrees. Defaults to branchstack.scope or worktree.",
+                )
+                .possible_values(&["worktree", "shared"])
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dirty")
+                .long("dirty")
+                .help(
+                    "What to do with uncommitted changes when switching \
+                     branches: refuse to switch, carry them along, or \
+                     stash them and re-apply them when coming back. \
+                     Defaults to branchstack.dirty or carry.",
+                )
+                .possible_values(&["abort", "carry", "autostash"])
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("backend")
+                .long("backend")
+                .help(
+                    "How to work with the repository: in-process with \
+                     libgit2 or gitoxide, whichever are built in, or by \
+                     running git to check out branches, which also runs \
+                     hooks and filters. Defaults to branchstack.backend, or \
+                     to libgit2 if it's built in and gix if it isn't.",
+                )
+                .possible_values(&["libgit2", "git", "gix"])
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("recurse-submodules")
+                .long("recurse-submodules")
+                .help(
+                    "Update submodules to the commits recorded on the branch \
+                     that's switched to. Defaults to submodule.recurse.",
+                )
+                .overrides_with("no-recurse-submodules")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("no-recurse-submodules")
+                .long("no-recurse-submodules")
+                .help("Leave submodules where they are when switching branches.")
+                .overrides_with("recurse-submodules")
+                .global(true),
+        )
         .subcommand(
             SubCommand::with_name("push")
                 .about("Pushes a new branch onto tho stack.")
+                .setting(AppSettings::AllowInvalidUtf8)
                 .arg(
                     Arg::with_name("branch")
                         .help(
-                            "The name of the branch to switch to. A number \
-                             like +1 or -1 rotates the stack until that \
-                             number (starting at 0, or counting from the \
-                             right for negative numbers) branch in on top.",
+                            "The branch to switch to. This can also be a \
+                             tag, a remote-tracking branch, or any revision \
+                             that git rev-parse understands, such as HEAD~3 \
+                             or a short commit id. Anything but a local \
+                             branch is checked out with a detached HEAD. A \
+                             number like +1 or -1 rotates the stack until \
+                             that number (starting at 0, or counting from \
+                             the right for negative numbers) branch in on \
+                             top. With --create, this is where the new \
+                             branch starts.",
                         )
-                        .required(true)
+                        .required_unless_one(&["create", "new-branch"])
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("create")
+                        .short("c")
+                        .long("create")
+                        .help(
+                            "Create a new branch with this name and switch \
+                             to it. The branch starts at HEAD, or at the \
+                             revision given as the branch argument.",
+                        )
+                        .value_name("new-branch")
+                        .conflicts_with("new-branch")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("new-branch")
+                        .short("b")
+                        .help("The same as --create.")
+                        .value_name("new-branch")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("temp")
+                        .short("t")
+                        .long("temp")
+                        .help(
+                            "Check out the branch argument on a new temporary \
+                             branch. Popping back off it deletes the branch, \
+                             unless it has commits that aren't anywhere else.",
+                        )
+                        .conflicts_with_all(&["create", "new-branch"])
+                        .requires("branch"),
+                )
+                .arg(Arg::with_name("force").short("f").long("force").help(
+                    "If local changes would be overwritten by switching \
+                     branches, list them and ask whether to discard them.",
+                ))
+                .arg(
+                    Arg::with_name("ignore-other-worktrees")
+                        .long("ignore-other-worktrees")
+                        .help(
+                            "Check out the branch even if another worktree \
+                             already has it checked out.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List the branches in the branch stack.")
+                .arg(
+                    Arg::with_name("verbose")
+                        .short("v")
+                        .long("verbose")
+                        .help("Show which stack is listed and where it's stored."),
                 ),
         )
-        .subcommand(SubCommand::with_name("list").about("List the branches in the branch stack."))
         .subcommand(
             SubCommand::with_name("pop")
-                .about("Remove the top of the stack and change to the next one down."),
+                .about("Remove the top of the stack and change to the next one down.")
+                .arg(Arg::with_name("force").short("f").long("force").help(
+                    "If local changes would be overwritten by switching \
+                     branches, list them and ask whether to discard them.",
+                ))
+                .arg(
+                    Arg::with_name("ignore-other-worktrees")
+                        .long("ignore-other-worktrees")
+                        .help(
+                            "Check out the branch even if another worktree \
+                             already has it checked out.",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Undo the last change to the stack and return to the branch before it."),
+        )
+        .subcommand(
+            SubCommand::with_name("redo").about("Re-apply the last change that was undone."),
+        )
+        .subcommand(
+            SubCommand::with_name("continue")
+                .about("Finish an operation that was interrupted while changing branches."),
+        )
+        .subcommand(
+            SubCommand::with_name("abort")
+                .about("Roll back an operation that was interrupted while changing branches."),
+        )
+        .subcommand(
+            SubCommand::with_name("stacks")
+                .about("Manage the named stacks in this repository.")
+                .subcommand(SubCommand::with_name("list").about("List the stacks."))
+                .subcommand(
+                    SubCommand::with_name("create")
+                        .about("Create a new, empty stack.")
+                        .arg(Arg::with_name("name").required(true).takes_value(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("delete")
+                        .about("Delete a stack.")
+                        .arg(Arg::with_name("name").required(true).takes_value(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("switch")
+                        .about("Make a stack the one used by default.")
+                        .arg(Arg::with_name("name").required(true).takes_value(true)),
+                ),
         )
         .get_matches();
 
+    let options = parse_options(&arg_matches)?;
+    let action = parse_action(&arg_matches)?;
+    Ok((action, options))
+}
+
+/// Parse the options that apply to every command. Since these are global,
+/// they may have been given before or after the subcommand.
+fn parse_options<'a>(arg_matches: &ArgMatches<'a>) -> Result<Options> {
+    let subcommand_matches = arg_matches.subcommand().1;
+    let global_value = |name: &str| match utf8_value_of(arg_matches, name)? {
+        Some(value) => Ok(Some(value)),
+        None => subcommand_matches.map_or(Ok(None), |m| utf8_value_of(m, name)),
+    };
+    let stack = global_value("stack")?.map(String::from);
+    let scope = global_value("scope")?
+        .map(|scope| scope.parse::<StackScope>())
+        .transpose()?;
+    let dirty = global_value("dirty")?
+        .map(|dirty| dirty.parse::<DirtyPolicy>())
+        .transpose()?;
+    let backend = global_value("backend")?
+        .map(|backend| backend.parse::<BackendKind>())
+        .transpose()?;
+    let flag = |name: &str| subcommand_matches.is_some_and(|m| m.is_present(name));
+    let force = flag("force");
+    let ignore_other_worktrees = flag("ignore-other-worktrees");
+    let global_flag = |name: &str| arg_matches.is_present(name) || flag(name);
+    let recurse_submodules = if global_flag("recurse-submodules") {
+        Some(true)
+    } else if global_flag("no-recurse-submodules") {
+        Some(false)
+    } else {
+        None
+    };
+    Ok(Options {
+        stack,
+        scope,
+        dirty,
+        force,
+        ignore_other_worktrees,
+        recurse_submodules,
+        backend,
+    })
+}
+
+/// Parse the subcommand into the `Action` to run.
+fn parse_action<'a>(arg_matches: &ArgMatches<'a>) -> Result<Action> {
     if let Some(push_args) = arg_matches.subcommand_matches("push") {
         parse_push_args(push_args)
-    } else if arg_matches.subcommand_matches("list").is_some() {
-        Ok(Action::List)
+    } else if let Some(list_args) = arg_matches.subcommand_matches("list") {
+        Ok(Action::List {
+            verbose: list_args.is_present("verbose"),
+        })
     } else if arg_matches.subcommand_matches("pop").is_some() {
         Ok(Action::Pop)
+    } else if arg_matches.subcommand_matches("undo").is_some() {
+        Ok(Action::Undo)
+    } else if arg_matches.subcommand_matches("redo").is_some() {
+        Ok(Action::Redo)
+    } else if arg_matches.subcommand_matches("continue").is_some() {
+        Ok(Action::Continue)
+    } else if arg_matches.subcommand_matches("abort").is_some() {
+        Ok(Action::Abort)
+    } else if let Some(stacks_args) = arg_matches.subcommand_matches("stacks") {
+        parse_stacks_args(stacks_args)
     } else {
         Err(BranchStackError::InvalidCommandError)
     }
 }
 
 /// Parse command-line arguments into parameters for the `push` command.
+/// With `-c` or `-b`, the branch argument is the new branch's start point.
+/// With `--temp`, it's the revision to put the temporary branch at.
+///
+/// Only a plain push takes the branch as raw bytes; new branches and
+/// revisions have to be UTF-8.
 fn parse_push_args<'a>(push_args: &ArgMatches<'a>) -> Result<Action> {
+    let new_branch = match utf8_value_of(push_args, "create")? {
+        Some(branch) => Some(branch),
+        None => utf8_value_of(push_args, "new-branch")?,
+    };
+    if let Some(branch) = new_branch {
+        return Ok(Action::PushNew {
+            branch: branch.to_string(),
+            start_point: utf8_value_of(push_args, "branch")?.map(String::from),
+        });
+    }
+    if push_args.is_present("temp") {
+        return utf8_value_of(push_args, "branch")?
+            .map(|revision| Action::PushTemp(revision.to_string()))
+            .ok_or_else(|| BranchStackError::ArgError(String::from("branch")));
+    }
+
     push_args
-        .value_of("branch")
-        .map(|branch_name| {
-            if let Some((dir, n)) = parse_rotation(branch_name) {
-                Action::Rotate(dir, n)
-            } else {
-                Action::Push(branch_name.to_string())
-            }
-        })
+        .value_of_os("branch")
+        .map(
+            |branch_name| match branch_name.to_str().and_then(parse_rotation) {
+                Some((dir, n)) => Action::Rotate(dir, n),
+                None => Action::Push(os_str_bytes(branch_name)),
+            },
+        )
         .ok_or_else(|| BranchStackError::ArgError(String::from("branch")))
 }
+
+/// The value of the argument `name`, if it was given. If it isn't valid
+/// UTF-8, this returns `Err(BranchStackError::ArgError)`.
+fn utf8_value_of<'a>(args: &'a ArgMatches<'_>, name: &str) -> Result<Option<&'a str>> {
+    args.value_of_os(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok_or_else(|| BranchStackError::ArgError(name.to_string()))
+        })
+        .transpose()
+}
+
+/// The bytes of a command-line argument. Outside of Unix, arguments are
+/// always Unicode, so they're just encoded as UTF-8.
+#[cfg(unix)]
+fn os_str_bytes(value: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    value.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn os_str_bytes(value: &OsStr) -> Vec<u8> {
+    value.to_string_lossy().into_owned().into_bytes()
+}
+
+/// Parse command-line arguments into parameters for the `stacks` command.
+/// With no subcommand, this lists the stacks.
+fn parse_stacks_args<'a>(stacks_args: &ArgMatches<'a>) -> Result<Action> {
+    let name = |args: &ArgMatches<'a>| {
+        args.value_of("name")
+            .map(String::from)
+            .ok_or_else(|| BranchStackError::ArgError(String::from("name")))
+    };
+    let stacks_action = match stacks_args.subcommand() {
+        ("create", Some(args)) => StacksAction::Create(name(args)?),
+        ("delete", Some(args)) => StacksAction::Delete(name(args)?),
+        ("switch", Some(args)) => StacksAction::Switch(name(args)?),
+        _ => StacksAction::List,
+    };
+    Ok(Action::Stacks(stacks_action))
+}
@@ -1,29 +1,31 @@
 /// # The File Stack
 ///
 /// This is the core data type for the branch stack plugin. It's a stack
-/// of strings that is persisted to a file on disc.
+/// of entries that is persisted to a file on disc. Each entry names a
+/// local branch, a tag, a remote-tracking branch, or a commit, along with
+/// some metadata about when it was pushed.
 ///
 /// ```
 /// # use tempfile::NamedTempFile;
 /// # use spectral::prelude::*;
-/// # use git_branch_stack::stack::FileStack;
+/// # use git_branch_stack::stack::{FileStack, Stack};
 ///
 /// let stack_file = NamedTempFile::new().unwrap();
 ///
 /// {
 ///     let mut stack = FileStack::new(&stack_file.path()).unwrap();
 ///
-///     stack.push("a".to_string());
-///     stack.push("b".to_string());
-///     stack.push("c".to_string());
+///     stack.push("a");
+///     stack.push("b");
+///     stack.push("c");
 ///     assert_that(&stack.len()).is_equal_to(3);
-///     assert_that(&stack.iter().collect::<Vec<&String>>()).is_equal_to(
-///         &vec![&"c".to_string(), &"b".to_string(), &"a".to_string()]
-///     );
-///     assert_that(&stack.peek()).is_some().is_equal_to(&String::from("c"));
-///     assert_that(&stack.pop()).is_some().is_equal_to(&String::from("c"));
+///     assert_that(&stack.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>())
+///         .is_equal_to(&vec!["c", "b", "a"]);
+///     assert_that(&stack.peek().map(|e| e.name)).is_some().is_equal_to(&String::from("c"));
+///     assert_that(&stack.pop().map(|e| e.name)).is_some().is_equal_to(&String::from("c"));
 ///     assert_that(&stack.len()).is_equal_to(2);
-///     // stack goes out of scope and writes the stack here.
+///     // nothing is written until the stack is saved.
+///     stack.save().unwrap();
 /// }
 ///
 /// {
@@ -31,66 +33,372 @@
 ///     let mut stack = FileStack::new(&stack_file.path()).unwrap();
 ///
 ///     assert_that(&stack.len()).is_equal_to(2);
-///     assert_that(&stack.pop()).is_some().is_equal_to(&String::from("b"));
-///     assert_that(&stack.pop()).is_some().is_equal_to(&String::from("a"));
+///     assert_that(&stack.pop().map(|e| e.name)).is_some().is_equal_to(&String::from("b"));
+///     assert_that(&stack.pop().map(|e| e.name)).is_some().is_equal_to(&String::from("a"));
 /// }
 /// ```
+///
+/// ## The `Stack` Trait
+///
+/// The stack operations (`push`, `pop`, `peek`, `rotate_up`, and so on) are
+/// provided by the `Stack` trait. An implementation only has to give access
+/// to its entries and say how to save them. `FileStack` is the one that's
+/// persisted, and `MemoryStack` never leaves memory, which is handy for
+/// tests or for embedding the actions with some other storage.
+///
+/// ## File Format
+///
+/// The stack is saved as a JSON document with a version header:
+///
+/// ```json
+/// {
+///   "version": 2,
+///   "entries": [
+///     { "name": "master", "pushed_at": 1570000000, "head": "0a1b2c..." },
+///     { "name": "0a1b2c...", "kind": "commit", "head": "0a1b2c..." }
+///   ]
+/// }
+/// ```
+///
+/// An entry without a `kind` is a local branch. Version 1 files didn't
+/// have kinds at all, and older stack files still have one branch name per
+/// line. Both are still read, and they're upgraded to the current format
+/// the next time the stack is saved.
+///
+/// git allows ref names that aren't valid UTF-8, so names are handled as
+/// bytes wherever they come from git or the command line. For those
+/// names, `name` is a lossy copy for display, and the real name is kept as
+/// an array of bytes in `raw_name`.
+///
+/// ## Storage
+///
+/// By default the document is kept in the stack file itself. With
+/// `Storage::Ref`, it's committed as `stack.json` under a git ref instead,
+/// such as `refs/branch-stack/default`. Each save adds a commit on top of
+/// the last one, so the ref has the stack's history, and it can be pushed
+/// to and fetched from a remote like any other ref. The stack file's path
+/// is still used for the lock and the journal.
+///
+/// ## Locking
+///
+/// While a `FileStack` is open, it holds `BRANCH_STACK.lock`, so only one
+/// process can read, change, and save the stack at a time. Saving writes a
+/// temporary file and renames it over the stack file.
+///
+/// Changes are only persisted by calling `save`. Dropping a stack without
+/// saving it throws away any changes and releases the lock.
+use std::collections::vec_deque;
 use std::collections::VecDeque;
+use std::fmt;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::iter::{IntoIterator, Iterator};
+use std::io::Read;
+use std::iter::{FromIterator, IntoIterator, Iterator};
 use std::path::{Path, PathBuf};
+use std::result;
+use std::str;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::backend::{open_backend, BackendKind};
 use crate::errors::{BranchStackError, Result};
+use crate::lock::{write_atomic, LockFile};
 
-/// The core FileStack struct.
-#[derive(Debug)]
-pub struct FileStack {
-    filename: PathBuf,
-    stack: VecDeque<String>,
+/// The version of the stack file format that this writes.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// What a stack entry refers to.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A local branch. Checking it out puts HEAD on the branch.
+    #[default]
+    Branch,
+    /// A tag. Checking it out detaches HEAD at the tagged commit.
+    Tag,
+    /// A remote-tracking branch, such as `origin/master`. Checking it out
+    /// detaches HEAD at the commit it points to.
+    Remote,
+    /// A single commit, named by its full id. This is how a detached HEAD
+    /// is pushed.
+    Commit,
 }
 
-impl FileStack {
-    /// Creates a new FileStack given a file name. IO problems could raise
-    /// an error.
-    pub fn new<P: AsRef<Path>>(filename: &P) -> Result<FileStack> {
-        let stack = FileStack::read_file(&filename)?;
-        Ok(FileStack {
-            filename: PathBuf::from(&filename.as_ref()),
-            stack,
-        })
+impl EntryKind {
+    /// The prefix that turns an entry's name into a full ref name. Commits
+    /// don't have one.
+    pub fn ref_prefix(self) -> Option<&'static str> {
+        match self {
+            EntryKind::Branch => Some("refs/heads/"),
+            EntryKind::Tag => Some("refs/tags/"),
+            EntryKind::Remote => Some("refs/remotes/"),
+            EntryKind::Commit => None,
+        }
+    }
+
+    fn is_branch(&self) -> bool {
+        *self == EntryKind::Branch
+    }
+}
+
+/// A single item on the stack.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct StackEntry {
+    /// The name of the branch, tag, or remote-tracking branch, or the id of
+    /// the commit. If the ref's name isn't valid UTF-8, this is a lossy copy
+    /// for showing to the user, and the real name is in `raw_name`.
+    pub name: String,
+    /// The bytes of the ref's name, when they aren't valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_name: Option<Vec<u8>>,
+    /// What `name` refers to.
+    #[serde(default, skip_serializing_if = "EntryKind::is_branch")]
+    pub kind: EntryKind,
+    /// When the entry was pushed, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pushed_at: Option<u64>,
+    /// The commit the entry pointed to when it was pushed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+    /// For a commit entry, the revision the user named it by, such as
+    /// `HEAD~1`. Checking it out says this in the reflog, the way git does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    /// A temporary branch that was created and checked out when this entry
+    /// was pushed. Popping back to the entry deletes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_branch: Option<String>,
+    /// The stash holding the changes that were left on the entry when it
+    /// was pushed. Checking the entry out again re-applies it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stash: Option<String>,
+}
+
+impl StackEntry {
+    /// Create a branch entry with a name and no metadata.
+    pub fn new<S: Into<String>>(name: S) -> StackEntry {
+        StackEntry {
+            name: name.into(),
+            raw_name: None,
+            kind: EntryKind::Branch,
+            pushed_at: None,
+            head: None,
+            revision: None,
+            temp_branch: None,
+            stash: None,
+        }
+    }
+
+    /// Create a branch entry from the raw bytes of its name.
+    pub fn from_bytes(name: &[u8]) -> StackEntry {
+        match str::from_utf8(name) {
+            Ok(name) => StackEntry::new(name),
+            Err(_) => StackEntry {
+                raw_name: Some(name.to_vec()),
+                ..StackEntry::new(String::from_utf8_lossy(name))
+            },
+        }
+    }
+
+    /// Create an entry for the commit with the full id `oid`.
+    pub fn commit<S: Into<String>>(oid: S) -> StackEntry {
+        let oid = oid.into();
+        StackEntry::new(oid.clone())
+            .with_kind(EntryKind::Commit)
+            .with_head(oid)
+    }
+
+    /// Record the revision the user named a commit entry by.
+    pub fn with_revision<S: Into<String>>(mut self, revision: S) -> StackEntry {
+        self.revision = Some(revision.into());
+        self
+    }
+
+    /// Set what the entry refers to.
+    pub fn with_kind(mut self, kind: EntryKind) -> StackEntry {
+        self.kind = kind;
+        self
+    }
+
+    /// Mark the entry as the one that the temporary branch `branch_name`
+    /// was pushed from.
+    pub fn with_temp_branch<S: Into<String>>(mut self, branch_name: S) -> StackEntry {
+        self.temp_branch = Some(branch_name.into());
+        self
+    }
+
+    /// Is this entry a local branch?
+    pub fn is_branch(&self) -> bool {
+        self.kind.is_branch()
+    }
+
+    /// The bytes of the entry's name, exactly as git has them.
+    pub fn name_bytes(&self) -> &[u8] {
+        match self.raw_name {
+            Some(ref raw_name) => raw_name,
+            None => self.name.as_bytes(),
+        }
+    }
+
+    /// What checking the entry out moves HEAD to, as the reflog puts it:
+    /// the revision it was named by, if there is one, or else its name.
+    pub fn reflog_name(&self) -> &[u8] {
+        match self.revision {
+            Some(ref revision) => revision.as_bytes(),
+            None => self.name_bytes(),
+        }
+    }
+
+    /// The full name of the ref the entry refers to, such as
+    /// `refs/tags/v1.0`. Commits don't have one. For a name that isn't
+    /// valid UTF-8, this is lossy; use `refname_bytes` to look it up.
+    pub fn refname(&self) -> Option<String> {
+        self.kind
+            .ref_prefix()
+            .map(|prefix| format!("{}{}", prefix, self.name))
+    }
+
+    /// The full name of the ref the entry refers to, as bytes.
+    pub fn refname_bytes(&self) -> Option<Vec<u8>> {
+        self.kind
+            .ref_prefix()
+            .map(|prefix| [prefix.as_bytes(), self.name_bytes()].concat())
+    }
+
+    /// Stamp the entry with the current time.
+    pub fn pushed_now(mut self) -> StackEntry {
+        self.pushed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs());
+        self
+    }
+
+    /// Record the commit that the entry's branch points to.
+    pub fn with_head<S: Into<String>>(mut self, head: S) -> StackEntry {
+        self.head = Some(head.into());
+        self
+    }
+}
+
+impl From<String> for StackEntry {
+    fn from(name: String) -> Self {
+        StackEntry::new(name)
+    }
+}
+
+impl From<&str> for StackEntry {
+    fn from(name: &str) -> Self {
+        StackEntry::new(name)
+    }
+}
+
+/// Read a stack entry that may have been written as a bare branch name.
+/// Journals and intent records from before entries had kinds stored the
+/// current branch that way.
+pub fn entry_or_name<'de, D>(deserializer: D) -> result::Result<StackEntry, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EntryOrName {
+        Name(String),
+        Entry(StackEntry),
+    }
+
+    Ok(match EntryOrName::deserialize(deserializer)? {
+        EntryOrName::Name(name) => StackEntry::new(name),
+        EntryOrName::Entry(entry) => entry,
+    })
+}
+
+impl fmt::Display for StackEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// The name of the file that holds the stack in a ref's tree.
+const REF_STACK_FILE: &str = "stack.json";
+
+/// Where the entries of a stack are persisted.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Storage {
+    /// In the stack file.
+    #[default]
+    File,
+    /// In a commit that `refname` points to in the repository whose git
+    /// directory is `git_dir`.
+    Ref { git_dir: PathBuf, refname: String },
+}
+
+/// The on-disc representation of the stack.
+#[derive(Debug, Deserialize, Serialize)]
+struct StackFile {
+    version: u32,
+    entries: VecDeque<StackEntry>,
+}
+
+/// A stack of entries. Implementors provide access to the entries and a way
+/// to persist them; everything else is built on top of that.
+pub trait Stack {
+    /// The entries in the stack, from the top down.
+    fn entries(&self) -> &VecDeque<StackEntry>;
+
+    /// The entries in the stack, from the top down, for changing.
+    fn entries_mut(&mut self) -> &mut VecDeque<StackEntry>;
+
+    /// Persist the stack. Until this is called, changes only live in memory.
+    fn save(&self) -> Result<()>;
+
+    /// The stack file and storage that this stack can be opened again from.
+    /// Interrupted operations can only be recovered for stacks that have
+    /// one. By default, this is `None`.
+    fn location(&self) -> Option<(&Path, &Storage)> {
+        None
     }
 
     /// The number of items in the stack.
-    pub fn len(&self) -> usize {
-        self.stack.len()
+    fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// Is the stack empty?
+    fn is_empty(&self) -> bool {
+        self.entries().is_empty()
     }
 
     /// Add an item to the top of the stack.
-    pub fn push(&mut self, item: String) {
-        self.stack.push_front(item);
+    fn push<E: Into<StackEntry>>(&mut self, item: E)
+    where
+        Self: Sized,
+    {
+        self.entries_mut().push_front(item.into());
     }
 
     /// Remove an item from the top of the stack and return it.
-    pub fn pop(&mut self) -> Option<String> {
-        self.stack.pop_front()
+    fn pop(&mut self) -> Option<StackEntry> {
+        self.entries_mut().pop_front()
     }
 
     /// What's on top of the stack?
-    pub fn peek(&self) -> Option<String> {
-        self.stack.front().cloned()
+    fn peek(&self) -> Option<StackEntry> {
+        self.entries().front().cloned()
     }
 
     /// Move something buried to the top of the stack.
     ///
     /// This is analogous to `pushd` with a positive number.
-    pub fn rotate_up(&mut self, n: usize) -> Result<()> {
+    fn rotate_up(&mut self, n: usize) -> Result<()> {
         let n = n + 1;
+        let stack = self.entries_mut();
 
-        if n <= self.stack.len() {
+        if n <= stack.len() {
             for _ in 0..n {
-                if let Some(item) = self.stack.pop_back() {
-                    self.stack.push_front(item);
+                if let Some(item) = stack.pop_back() {
+                    stack.push_front(item);
                 }
             }
             Ok(())
@@ -102,11 +410,13 @@ impl FileStack {
     /// Move something buried to the bottom of the stack.
     ///
     /// This is analogous to `pushd` with a negative number.
-    pub fn rotate_down(&mut self, n: usize) -> Result<()> {
-        if n < self.stack.len() {
+    fn rotate_down(&mut self, n: usize) -> Result<()> {
+        let stack = self.entries_mut();
+
+        if n < stack.len() {
             for _ in 0..n {
-                if let Some(item) = self.stack.pop_front() {
-                    self.stack.push_back(item);
+                if let Some(item) = stack.pop_front() {
+                    stack.push_back(item);
                 }
             }
             Ok(())
@@ -115,41 +425,210 @@ impl FileStack {
         }
     }
 
+    /// Replace everything in the stack with `entries`, given from the top
+    /// down.
+    fn replace<I: IntoIterator<Item = StackEntry>>(&mut self, entries: I)
+    where
+        Self: Sized,
+    {
+        *self.entries_mut() = entries.into_iter().collect();
+    }
+
     /// Iterate over all of the items in the stack from top down.
-    pub fn iter(&self) -> impl Iterator<Item = &String> {
-        self.stack.iter()
+    fn iter(&self) -> vec_deque::Iter<'_, StackEntry> {
+        self.entries().iter()
     }
 
-    fn read_file<P: AsRef<Path>>(path: &P) -> Result<VecDeque<String>> {
+    /// Run `f` against the stack as a transaction. If `f` succeeds, the
+    /// stack is saved. If it fails, the stack is restored to exactly what it
+    /// was before `f` ran, and nothing is written.
+    fn transaction<F, R>(&mut self, f: F) -> Result<R>
+    where
+        Self: Sized,
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        let snapshot = self.entries().clone();
+        let result = f(self).and_then(|value| self.save().map(|_| value));
+        if result.is_err() {
+            *self.entries_mut() = snapshot;
+        }
+        result
+    }
+}
+
+/// A stack that only lives in memory. Saving it does nothing. This is
+/// useful for embedding the actions with other storage, and for testing.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MemoryStack {
+    stack: VecDeque<StackEntry>,
+}
+
+impl MemoryStack {
+    /// Creates an empty stack.
+    pub fn new() -> MemoryStack {
+        MemoryStack::default()
+    }
+}
+
+impl Stack for MemoryStack {
+    fn entries(&self) -> &VecDeque<StackEntry> {
+        &self.stack
+    }
+
+    fn entries_mut(&mut self) -> &mut VecDeque<StackEntry> {
+        &mut self.stack
+    }
+
+    fn save(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<E: Into<StackEntry>> FromIterator<E> for MemoryStack {
+    /// Build a stack from entries given from the top down.
+    fn from_iter<I: IntoIterator<Item = E>>(entries: I) -> Self {
+        MemoryStack {
+            stack: entries.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// The core FileStack struct.
+#[derive(Debug)]
+pub struct FileStack {
+    filename: PathBuf,
+    storage: Storage,
+    stack: VecDeque<StackEntry>,
+    _lock: LockFile,
+}
+
+impl FileStack {
+    /// Creates a new FileStack given a file name. This locks the file until
+    /// the stack is dropped. If another process has it locked, or if there
+    /// are IO problems, this returns an error.
+    pub fn new<P: AsRef<Path>>(filename: &P) -> Result<FileStack> {
+        FileStack::open(filename, Storage::File)
+    }
+
+    /// Opens a stack that's persisted in `storage`. `filename` is still
+    /// locked, and it identifies the stack to the journal.
+    pub fn open<P: AsRef<Path>>(filename: &P, storage: Storage) -> Result<FileStack> {
+        let lock = LockFile::acquire(filename)?;
+        FileStack::read(filename, storage, lock)
+    }
+
+    /// Opens a stack like `open` does, but takes over its lock if a process
+    /// that died left it behind. This is for finishing or rolling back an
+    /// operation that was interrupted.
+    pub fn recover<P: AsRef<Path>>(filename: &P, storage: Storage) -> Result<FileStack> {
+        let lock = LockFile::take_over(filename)?;
+        FileStack::read(filename, storage, lock)
+    }
+
+    fn read<P: AsRef<Path>>(filename: &P, storage: Storage, lock: LockFile) -> Result<FileStack> {
+        let stack = match storage {
+            Storage::File => FileStack::read_file(&filename)?,
+            Storage::Ref {
+                ref git_dir,
+                ref refname,
+            } => FileStack::read_ref(git_dir, refname)?,
+        };
+        Ok(FileStack {
+            filename: PathBuf::from(&filename.as_ref()),
+            storage,
+            stack,
+            _lock: lock,
+        })
+    }
+
+    /// The file the stack is stored in. With `Storage::Ref`, this is only
+    /// used for the lock and the journal.
+    pub fn path(&self) -> &Path {
+        &self.filename
+    }
+
+    /// Where the stack's entries are persisted.
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    fn read_file<P: AsRef<Path>>(path: &P) -> Result<VecDeque<StackEntry>> {
         if !path.as_ref().exists() {
-            Ok(VecDeque::new())
+            return Ok(VecDeque::new());
+        }
+
+        let mut file = File::open(path)?;
+        let mut buffer = String::new();
+        file.read_to_string(&mut buffer)?;
+        FileStack::parse(&buffer)
+    }
+
+    fn read_ref(git_dir: &Path, refname: &str) -> Result<VecDeque<StackEntry>> {
+        let repo = open_backend(git_dir, BackendKind::default())?;
+        match repo.read_ref_file(refname, REF_STACK_FILE)? {
+            Some(contents) => FileStack::parse(&String::from_utf8_lossy(&contents)),
+            None => Ok(VecDeque::new()),
+        }
+    }
+
+    fn parse(buffer: &str) -> Result<VecDeque<StackEntry>> {
+        if buffer.trim_start().starts_with('{') {
+            let stack_file: StackFile = serde_json::from_str(buffer)?;
+            if stack_file.version > FORMAT_VERSION {
+                return Err(BranchStackError::UnsupportedStackVersion(
+                    stack_file.version,
+                ));
+            }
+            Ok(stack_file.entries)
         } else {
-            let mut file = File::open(&path)?;
-            let mut buffer = String::new();
-            file.read_to_string(&mut buffer)?;
+            // This is the original format: one branch name per line.
             Ok(buffer
                 .lines()
-                .map(|line: &str| line.trim().to_string())
+                .map(|line: &str| line.trim())
+                .filter(|line| !line.is_empty())
+                .map(StackEntry::from)
                 .collect())
         }
     }
+}
+
+impl Stack for FileStack {
+    fn entries(&self) -> &VecDeque<StackEntry> {
+        &self.stack
+    }
+
+    fn entries_mut(&mut self) -> &mut VecDeque<StackEntry> {
+        &mut self.stack
+    }
 
+    /// Write the stack to its storage.
     fn save(&self) -> Result<()> {
-        let mut file = File::create(&self.filename)?;
-        self.stack
-            .iter()
-            .try_for_each(|item| writeln!(file, "{}", item).map_err(BranchStackError::from))
+        let stack_file = StackFile {
+            version: FORMAT_VERSION,
+            entries: self.stack.clone(),
+        };
+        let mut buffer = serde_json::to_vec_pretty(&stack_file)?;
+        buffer.push(b'\n');
+        match self.storage {
+            Storage::File => write_atomic(&self.filename, &buffer),
+            Storage::Ref {
+                ref git_dir,
+                ref refname,
+            } => {
+                let repo = open_backend(git_dir, BackendKind::default())?;
+                repo.commit_ref_file(refname, REF_STACK_FILE, &buffer, "update branch stack")?;
+                Ok(())
+            }
+        }
     }
-}
 
-impl Drop for FileStack {
-    fn drop(&mut self) {
-        let _ = self.save();
+    fn location(&self) -> Option<(&Path, &Storage)> {
+        Some((&self.filename, &self.storage))
     }
 }
 
 impl IntoIterator for FileStack {
-    type Item = String;
+    type Item = StackEntry;
     type IntoIter = ::std::collections::vec_deque::IntoIter<Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
@@ -163,7 +642,7 @@ mod tests {
     use super::*;
 
     use std::fs::File;
-    use std::io::Write;
+    use std::io::{Read, Write};
     use std::path::Path;
 
     use assert_fs::fixture::NamedTempFile;
@@ -189,7 +668,7 @@ mod tests {
     }
 
     fn assert_stack(stack: FileStack, expected: Vec<&str>) {
-        assert_that(&stack.into_iter().collect::<Vec<String>>()).is_equal_to(
+        assert_that(&stack.into_iter().map(|e| e.name).collect::<Vec<String>>()).is_equal_to(
             expected
                 .into_iter()
                 .map(String::from)
@@ -251,13 +730,13 @@ mod tests {
         );
         let mut stack = FileStack::new(&stack_file.path()).unwrap();
         assert_that(&stack.len()).is_equal_to(7);
-        assert_that(&stack.peek())
+        assert_that(&stack.peek().map(|e| e.name))
             .is_some()
-            .is_equal_to(&String::from("0"));
+            .is_equal_to(String::from("0"));
         stack.push("hello".to_string());
-        assert_that(&stack.peek())
+        assert_that(&stack.peek().map(|e| e.name))
             .is_some()
-            .is_equal_to(&String::from("hello"));
+            .is_equal_to(String::from("hello"));
     }
 
     #[test]
@@ -280,13 +759,13 @@ mod tests {
         );
         let mut stack = FileStack::new(&stack_file.path()).unwrap();
         assert_that(&stack.len()).is_equal_to(7);
-        assert_that(&stack.pop())
+        assert_that(&stack.pop().map(|e| e.name))
             .is_some()
             .is_equal_to(String::from("0"));
-        assert_that(&stack.pop())
+        assert_that(&stack.pop().map(|e| e.name))
             .is_some()
             .is_equal_to(String::from("1"));
-        assert_that(&stack.pop())
+        assert_that(&stack.pop().map(|e| e.name))
             .is_some()
             .is_equal_to(String::from("2"));
     }
@@ -303,20 +782,176 @@ mod tests {
     }
 
     #[test]
-    fn drop_saves_changes_for_new_file() {
+    fn save_writes_changes_for_new_file() {
         let stack_file = NamedTempFile::new("stack").unwrap();
         {
             let mut stack = FileStack::new(&stack_file.path()).unwrap();
             stack.push("Ford".to_string());
             stack.push("Arthur".to_string());
             stack.push("Trillian".to_string());
+            stack.save().unwrap();
         }
-        stack_file.assert(
-            r#"Trillian
-Arthur
-Ford
-"#,
+        stack_file.assert(predicates::str::contains("\"version\": 2"));
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_stack(stack, vec!["Trillian", "Arthur", "Ford"]);
+    }
+
+    #[test]
+    fn drop_without_save_discards_changes() {
+        let (stack_file, mut stack) = setup_stack(3);
+        stack.push("Marvin".to_string());
+        drop(stack);
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_stack(stack, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn replace_swaps_the_stack_contents() {
+        let (_stack_file, mut stack) = setup_stack(3);
+        stack.replace(vec![StackEntry::new("a"), StackEntry::new("b")]);
+        assert_stack(stack, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn transaction_saves_on_success() {
+        let (stack_file, mut stack) = setup_stack(2);
+        stack
+            .transaction(|stack| {
+                stack.pop();
+                Ok(())
+            })
+            .unwrap();
+        drop(stack);
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_stack(stack, vec!["1"]);
+    }
+
+    #[test]
+    fn transaction_restores_the_stack_on_failure() {
+        let (stack_file, mut stack) = setup_stack(3);
+        let result: Result<()> = stack.transaction(|stack| {
+            stack.push("Marvin".to_string());
+            stack.rotate_up(1)?;
+            Err(BranchStackError::NoStackEntry)
+        });
+        assert_that(&result).is_err();
+        assert_that(&stack.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>())
+            .is_equal_to(vec!["0", "1", "2"]);
+        drop(stack);
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_stack(stack, vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn save_upgrades_plain_text_files() {
+        let (stack_file, stack) = setup_stack(3);
+        stack.save().unwrap();
+        drop(stack);
+        let mut buffer = String::new();
+        File::open(stack_file.path())
+            .unwrap()
+            .read_to_string(&mut buffer)
+            .unwrap();
+        let stack_file: StackFile = serde_json::from_str(&buffer).unwrap();
+        assert_that(&stack_file.version).is_equal_to(FORMAT_VERSION);
+        assert_that(&stack_file.entries.len()).is_equal_to(3);
+    }
+
+    #[test]
+    fn metadata_round_trips_through_the_file() {
+        let stack_file = NamedTempFile::new("stack").unwrap();
+        let entry = StackEntry::new("Zaphod")
+            .pushed_now()
+            .with_head("0123456789abcdef0123456789abcdef01234567");
+        {
+            let mut stack = FileStack::new(&stack_file.path()).unwrap();
+            stack.push(entry.clone());
+            stack.save().unwrap();
+        }
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_that(&stack.peek()).is_some().is_equal_to(&entry);
+        assert_that(&entry.pushed_at).is_some();
+    }
+
+    #[test]
+    fn kinds_round_trip_through_the_file() {
+        let stack_file = NamedTempFile::new("stack").unwrap();
+        let commit = StackEntry::commit("0123456789abcdef0123456789abcdef01234567");
+        let tag = StackEntry::new("v1.0").with_kind(EntryKind::Tag);
+        {
+            let mut stack = FileStack::new(&stack_file.path()).unwrap();
+            stack.push(commit.clone());
+            stack.push(tag.clone());
+            stack.push("master");
+            stack.save().unwrap();
+        }
+        stack_file.assert(predicates::str::contains("\"kind\": \"commit\""));
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_that(&stack.iter().cloned().collect::<Vec<StackEntry>>()).is_equal_to(vec![
+            StackEntry::new("master"),
+            tag,
+            commit,
+        ]);
+    }
+
+    #[test]
+    fn non_utf8_names_round_trip_through_the_file() {
+        let stack_file = NamedTempFile::new("stack").unwrap();
+        let entry = StackEntry::from_bytes(b"caf\xe9");
+        {
+            let mut stack = FileStack::new(&stack_file.path()).unwrap();
+            stack.push(entry.clone());
+            stack.push(StackEntry::from_bytes(b"master"));
+            stack.save().unwrap();
+        }
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_that(&stack.iter().cloned().collect::<Vec<StackEntry>>())
+            .is_equal_to(vec![StackEntry::new("master"), entry.clone()]);
+        assert_that(&entry.name_bytes()).is_equal_to(&b"caf\xe9"[..]);
+        assert_that(&entry.refname_bytes())
+            .is_some()
+            .is_equal_to(b"refs/heads/caf\xe9".to_vec());
+        assert_that(&entry.to_string()).is_equal_to("caf\u{fffd}".to_string());
+    }
+
+    #[test]
+    fn version_one_entries_are_branches() {
+        let stack_file = NamedTempFile::new("stack").unwrap();
+        create_stack_file(
+            &stack_file.path(),
+            vec![r#"{"version": 1, "entries": [{"name": "master"}]}"#.to_string()],
+        );
+        let stack = FileStack::new(&stack_file.path()).unwrap();
+        assert_that(&stack.peek().map(|e| e.kind))
+            .is_some()
+            .is_equal_to(EntryKind::Branch);
+    }
+
+    #[test]
+    fn new_fails_while_another_stack_holds_the_lock() {
+        let (stack_file, _stack) = setup_stack(2);
+        assert_that(&FileStack::new(&stack_file.path()))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::Locked(_)));
+    }
+
+    #[test]
+    fn drop_releases_the_lock() {
+        let (stack_file, stack) = setup_stack(2);
+        drop(stack);
+        assert_that(&FileStack::new(&stack_file.path())).is_ok();
+    }
+
+    #[test]
+    fn new_rejects_newer_format_versions() {
+        let stack_file = NamedTempFile::new("stack").unwrap();
+        create_stack_file(
+            &stack_file.path(),
+            vec![r#"{"version": 99, "entries": []}"#.to_string()],
         );
+        assert_that(&FileStack::new(&stack_file.path()))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::UnsupportedStackVersion(99)));
     }
 
     #[test]
@@ -327,8 +962,8 @@ Ford
             (0..7).map(|i| format!("{}", i)).collect(),
         );
         let stack = FileStack::new(&stack_file.path()).unwrap();
-        assert_that(&stack.into_iter().collect::<Vec<String>>()).is_equal_to(
-            &vec!["0", "1", "2", "3", "4", "5", "6"]
+        assert_that(&stack.into_iter().map(|e| e.name).collect::<Vec<String>>()).is_equal_to(
+            vec!["0", "1", "2", "3", "4", "5", "6"]
                 .into_iter()
                 .map(String::from)
                 .collect::<Vec<String>>(),
@@ -338,8 +973,8 @@ Ford
     #[test]
     fn iter_iterates_over_stack() {
         let (_stack_file, stack) = setup_stack(7);
-        assert_that(&stack.iter().collect::<Vec<&String>>()).is_equal_to(
-            &vec![
+        assert_that(&stack.iter().map(|e| &e.name).collect::<Vec<&String>>()).is_equal_to(
+            [
                 "0".to_string(),
                 "1".to_string(),
                 "2".to_string(),
@@ -386,10 +1021,7 @@ Ford
         let (_stack_file, mut stack) = setup_stack(4);
         assert_that(&stack.rotate_up(4))
             .is_err()
-            .matches(|v| match v {
-                BranchStackError::NoStackEntry => true,
-                _ => false,
-            });
+            .matches(|v| matches!(v, BranchStackError::NoStackEntry));
     }
 
     #[test]
@@ -425,9 +1057,31 @@ Ford
         let (_stack_file, mut stack) = setup_stack(4);
         assert_that(&stack.rotate_down(4))
             .is_err()
-            .matches(|v| match v {
-                BranchStackError::NoStackEntry => true,
-                _ => false,
-            });
+            .matches(|v| matches!(v, BranchStackError::NoStackEntry));
+    }
+
+    #[test]
+    fn memory_stack_shares_the_stack_operations() {
+        let mut stack: MemoryStack = vec!["0", "1", "2"].into_iter().collect();
+        stack.push("top");
+        assert_that(&stack.len()).is_equal_to(4);
+        assert_that(&stack.rotate_up(0)).is_ok();
+        assert_that(&stack.pop().map(|e| e.name))
+            .is_some()
+            .is_equal_to("2".to_string());
+        assert_that(&stack.iter().map(|e| e.name.as_str()).collect::<Vec<&str>>())
+            .is_equal_to(vec!["top", "0", "1"]);
+        assert_that(&stack.location()).is_none();
+    }
+
+    #[test]
+    fn memory_stack_transaction_rolls_back_on_failure() {
+        let mut stack: MemoryStack = vec!["0", "1"].into_iter().collect();
+        let result: Result<()> = stack.transaction(|stack| {
+            stack.pop();
+            Err(BranchStackError::EmptyStack)
+        });
+        assert_that(&result).is_err();
+        assert_that(&stack.len()).is_equal_to(2);
     }
 }
@@ -0,0 +1,444 @@
+/// # Named Stacks
+///
+/// A repository can have several stacks, each with its own name. The
+/// `default` stack lives in `BRANCH_STACK` in the git directory, where the
+/// single stack always has. Other stacks live in `branch-stacks/<name>`.
+///
+/// The stack to use when none is given on the command line is remembered in
+/// the `branchstack.stack` git config setting. That setting is shared by all
+/// of a repository's worktrees, so if the stack it names doesn't exist in
+/// the current worktree's scope, the default stack is used instead.
+///
+/// ## Worktrees
+///
+/// When a repository has linked worktrees (`git worktree add`), each
+/// worktree has its own private git directory, and they all share the main
+/// repository's git directory. Stacks can be kept in either place, chosen by
+/// the `StackScope`:
+///
+/// - `worktree` keeps the stacks in the current worktree's private git
+///   directory, so each worktree has its own stacks. This is the default. In
+///   the main worktree, the private and common directories are the same.
+/// - `shared` keeps the stacks in the common git directory, so every
+///   worktree sees the same stacks.
+///
+/// The scope is taken from the `--scope` option or the `branchstack.scope`
+/// git config setting.
+///
+/// ## Ref Storage
+///
+/// Setting `branchstack.storage` to `ref` keeps each stack under
+/// `refs/branch-stack/<name>` instead of in a file, so it can be pushed and
+/// fetched like any other ref:
+///
+/// ```sh
+/// git push origin 'refs/branch-stack/*:refs/branch-stack/*'
+/// ```
+///
+/// Refs are shared by all of a repository's worktrees, so stacks stored
+/// this way are always in the shared scope.
+use std::fmt;
+use std::fs::{create_dir_all, read_dir, remove_file};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::backend::GitBackend;
+use crate::errors::{BranchStackError, Result};
+use crate::git::common_dir;
+use crate::lock::{sibling, LockFile};
+use crate::stack::{FileStack, Stack, Storage};
+
+/// The name of the stack that's used when no other one is chosen.
+pub const DEFAULT_STACK: &str = "default";
+
+/// The git config setting that remembers the current stack.
+const CONFIG_KEY: &str = "branchstack.stack";
+
+/// The git config setting that chooses where stacks are stored.
+const SCOPE_CONFIG_KEY: &str = "branchstack.scope";
+
+/// The git config setting that chooses whether stacks are kept in files or
+/// refs.
+const STORAGE_CONFIG_KEY: &str = "branchstack.storage";
+
+/// The namespace that stacks are kept under with ref storage.
+pub const REF_PREFIX: &str = "refs/branch-stack/";
+
+/// Where the stacks for a worktree are stored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StackScope {
+    /// In the worktree's private git directory.
+    Worktree,
+    /// In the git directory shared by all worktrees.
+    Shared,
+}
+
+impl StackScope {
+    /// The scope set in the git config, or `Worktree` if there isn't one.
+    pub fn from_config(repo: &dyn GitBackend) -> Result<StackScope> {
+        match repo.config_string(SCOPE_CONFIG_KEY)? {
+            Some(scope) => scope.parse(),
+            None => Ok(StackScope::Worktree),
+        }
+    }
+
+    /// The directory that stacks in this scope are stored in.
+    pub fn dir(self, repo: &dyn GitBackend) -> Result<PathBuf> {
+        match self {
+            StackScope::Worktree => Ok(repo.git_dir().to_path_buf()),
+            StackScope::Shared => common_dir(repo),
+        }
+    }
+}
+
+impl FromStr for StackScope {
+    type Err = BranchStackError;
+
+    fn from_str(input: &str) -> Result<StackScope> {
+        match input {
+            "worktree" => Ok(StackScope::Worktree),
+            "shared" => Ok(StackScope::Shared),
+            _ => Err(BranchStackError::InvalidStackScope(input.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for StackScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackScope::Worktree => write!(f, "worktree"),
+            StackScope::Shared => write!(f, "shared"),
+        }
+    }
+}
+
+/// Suffixes of files that are kept next to a stack file.
+const SIDECAR_SUFFIXES: &[&str] = &[".lock", ".journal", ".tmp", ".intent"];
+
+/// Is this a name we can use for a stack? Names are made of letters,
+/// digits, `-`, `_`, and `.`, and they can't look like one of the files
+/// kept next to a stack.
+pub fn validate_stack_name(name: &str) -> Result<()> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if name.is_empty()
+        || !valid_chars
+        || name.starts_with('.')
+        || SIDECAR_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+    {
+        Err(BranchStackError::InvalidStackName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Are stacks kept in refs instead of files?
+fn uses_refs(repo: &dyn GitBackend) -> Result<bool> {
+    match repo.config_string(STORAGE_CONFIG_KEY)? {
+        Some(ref storage) if storage == "file" => Ok(false),
+        Some(ref storage) if storage == "ref" => Ok(true),
+        Some(storage) => Err(BranchStackError::InvalidStackStorage(storage)),
+        None => Ok(false),
+    }
+}
+
+/// The ref that the named stack is kept under with ref storage. Stack
+/// names are already limited to characters that are fine in a ref name, so
+/// the only names left that git won't take are ones with `..` in them or
+/// that end with `.`.
+fn stack_refname(name: &str) -> Result<String> {
+    validate_stack_name(name)?;
+    if name.contains("..") || name.ends_with('.') {
+        Err(BranchStackError::InvalidStackName(name.to_string()))
+    } else {
+        Ok(format!("{}{}", REF_PREFIX, name))
+    }
+}
+
+/// Where the named stack's entries are persisted.
+pub fn stack_storage(repo: &dyn GitBackend, name: &str) -> Result<Storage> {
+    if uses_refs(repo)? {
+        Ok(Storage::Ref {
+            git_dir: common_dir(repo)?,
+            refname: stack_refname(name)?,
+        })
+    } else {
+        Ok(Storage::File)
+    }
+}
+
+/// The file that the named stack is stored in. With ref storage, this is
+/// where its lock and journal are kept.
+pub fn stack_path(repo: &dyn GitBackend, scope: StackScope, name: &str) -> Result<PathBuf> {
+    validate_stack_name(name)?;
+    let dir = if uses_refs(repo)? {
+        common_dir(repo)?
+    } else {
+        scope.dir(repo)?
+    };
+    if name == DEFAULT_STACK {
+        Ok(dir.join("BRANCH_STACK"))
+    } else {
+        Ok(dir.join("branch-stacks").join(name))
+    }
+}
+
+/// The names of all of the stacks in the scope, sorted. The default stack
+/// is always included.
+pub fn list_stacks(repo: &dyn GitBackend, scope: StackScope) -> Result<Vec<String>> {
+    let mut names = vec![DEFAULT_STACK.to_string()];
+    if uses_refs(repo)? {
+        for (refname, _) in repo.refs(REF_PREFIX)? {
+            let name = refname.get(REF_PREFIX.len()..).unwrap_or_default();
+            if let Ok(name) = std::str::from_utf8(name) {
+                if validate_stack_name(name).is_ok() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        return Ok(names);
+    }
+
+    let stacks_dir = scope.dir(repo)?.join("branch-stacks");
+    if stacks_dir.is_dir() {
+        for dir_entry in read_dir(stacks_dir)? {
+            let dir_entry = dir_entry?;
+            if let Some(name) = dir_entry.file_name().to_str() {
+                if dir_entry.file_type()?.is_file() && validate_stack_name(name).is_ok() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Does the named stack exist? The default stack always does.
+pub fn stack_exists(repo: &dyn GitBackend, scope: StackScope, name: &str) -> Result<bool> {
+    if name == DEFAULT_STACK {
+        Ok(true)
+    } else if uses_refs(repo)? {
+        Ok(repo.find_ref(stack_refname(name)?.as_bytes())?.is_some())
+    } else {
+        Ok(stack_path(repo, scope, name)?.exists())
+    }
+}
+
+/// The name of the stack to use when none is given. If the remembered stack
+/// doesn't exist in `scope`, such as when it was chosen in another
+/// worktree, this is the default stack.
+pub fn current_stack_name(repo: &dyn GitBackend, scope: StackScope) -> Result<String> {
+    match repo.config_string(CONFIG_KEY)? {
+        Some(name) if stack_exists(repo, scope, &name).unwrap_or(false) => Ok(name),
+        _ => Ok(DEFAULT_STACK.to_string()),
+    }
+}
+
+/// Remember the stack to use when none is given.
+pub fn set_current_stack_name(repo: &dyn GitBackend, scope: StackScope, name: &str) -> Result<()> {
+    if !stack_exists(repo, scope, name)? {
+        return Err(BranchStackError::NoSuchStack(name.to_string()));
+    }
+    repo.set_config(CONFIG_KEY, name)
+}
+
+/// Open the named stack. It has to exist already.
+pub fn open_stack(repo: &dyn GitBackend, scope: StackScope, name: &str) -> Result<FileStack> {
+    if !stack_exists(repo, scope, name)? {
+        return Err(BranchStackError::NoSuchStack(name.to_string()));
+    }
+    let path = stack_path(repo, scope, name)?;
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    FileStack::open(&path, stack_storage(repo, name)?)
+}
+
+/// Create a new, empty stack.
+pub fn create_stack(repo: &dyn GitBackend, scope: StackScope, name: &str) -> Result<()> {
+    let path = stack_path(repo, scope, name)?;
+    if stack_exists(repo, scope, name)? {
+        return Err(BranchStackError::StackExists(name.to_string()));
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    FileStack::open(&path, stack_storage(repo, name)?)?.save()
+}
+
+/// Delete a stack along with its journal. The current stack can't be
+/// deleted.
+pub fn delete_stack(repo: &dyn GitBackend, scope: StackScope, name: &str) -> Result<()> {
+    let path = stack_path(repo, scope, name)?;
+    if !stack_exists(repo, scope, name)? {
+        return Err(BranchStackError::NoSuchStack(name.to_string()));
+    }
+    if current_stack_name(repo, scope)? == name {
+        return Err(BranchStackError::StackInUse(name.to_string()));
+    }
+
+    let _lock = LockFile::acquire(&path)?;
+    if uses_refs(repo)? {
+        repo.delete_ref(&stack_refname(name)?)?;
+    }
+    for file in &[path.clone(), sibling(&path, ".journal")] {
+        if file.exists() {
+            remove_file(file)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use git2::Repository;
+    use spectral::prelude::*;
+    use tempfile::{tempdir, TempDir};
+
+    use crate::backend::{open_backend, BackendKind};
+
+    const SCOPE: StackScope = StackScope::Worktree;
+
+    fn setup_repo() -> (TempDir, Repository) {
+        let working_dir = tempdir().unwrap();
+        let repo = Repository::init(working_dir.path()).unwrap();
+        (working_dir, repo)
+    }
+
+    /// Open `repo` with the default backend. Backends may read the config
+    /// once when they're opened, so this is done after it's set up.
+    fn open(repo: &Repository) -> Box<dyn GitBackend> {
+        open_backend(repo.path(), BackendKind::default()).unwrap()
+    }
+
+    #[test]
+    fn validate_stack_name_rejects_bad_names() {
+        for name in &["", ".hidden", "a/b", "x.lock", "x.journal", "sp ace"] {
+            assert_that(&validate_stack_name(name)).is_err();
+        }
+        for name in &["default", "release-1.2", "review_stack"] {
+            assert_that(&validate_stack_name(name)).is_ok();
+        }
+    }
+
+    #[test]
+    fn default_stack_uses_the_original_file() {
+        let (_working_dir, repo) = setup_repo();
+        let backend = open(&repo);
+        assert_that(&stack_path(&*backend, StackScope::Worktree, DEFAULT_STACK).unwrap())
+            .is_equal_to(repo.path().join("BRANCH_STACK"));
+    }
+
+    #[test]
+    fn scopes_share_a_directory_outside_of_linked_worktrees() {
+        let (_working_dir, repo) = setup_repo();
+        let backend = open(&repo);
+        assert_that(&StackScope::Worktree.dir(&*backend).unwrap())
+            .is_equal_to(StackScope::Shared.dir(&*backend).unwrap());
+    }
+
+    #[test]
+    fn scope_parses_and_comes_from_config() {
+        let (_working_dir, repo) = setup_repo();
+        assert_that(&"bogus".parse::<StackScope>()).is_err();
+        assert_that(&StackScope::from_config(&*open(&repo)).unwrap())
+            .is_equal_to(StackScope::Worktree);
+        repo.config()
+            .unwrap()
+            .set_str("branchstack.scope", "shared")
+            .unwrap();
+        assert_that(&StackScope::from_config(&*open(&repo)).unwrap())
+            .is_equal_to(StackScope::Shared);
+    }
+
+    #[test]
+    fn create_list_and_delete_stacks() {
+        let (_working_dir, repo) = setup_repo();
+        let backend = open(&repo);
+        create_stack(&*backend, SCOPE, "review").unwrap();
+        create_stack(&*backend, SCOPE, "feature").unwrap();
+        assert_that(&create_stack(&*backend, SCOPE, "review"))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::StackExists(_)));
+        assert_that(&list_stacks(&*backend, SCOPE).unwrap()).is_equal_to(vec![
+            "default".to_string(),
+            "feature".to_string(),
+            "review".to_string(),
+        ]);
+
+        delete_stack(&*backend, SCOPE, "review").unwrap();
+        assert_that(&list_stacks(&*backend, SCOPE).unwrap())
+            .is_equal_to(vec!["default".to_string(), "feature".to_string()]);
+        assert_that(&delete_stack(&*backend, SCOPE, "review"))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::NoSuchStack(_)));
+    }
+
+    #[test]
+    fn current_stack_is_remembered() {
+        let (_working_dir, repo) = setup_repo();
+        let backend = open(&repo);
+        assert_that(&current_stack_name(&*backend, SCOPE).unwrap())
+            .is_equal_to(DEFAULT_STACK.to_string());
+        assert_that(&set_current_stack_name(&*backend, SCOPE, "missing")).is_err();
+
+        create_stack(&*backend, SCOPE, "feature").unwrap();
+        set_current_stack_name(&*backend, SCOPE, "feature").unwrap();
+        assert_that(&current_stack_name(&*backend, SCOPE).unwrap())
+            .is_equal_to("feature".to_string());
+        assert_that(&delete_stack(&*backend, SCOPE, "feature"))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::StackInUse(_)));
+    }
+
+    #[test]
+    fn ref_storage_keeps_stacks_in_refs() {
+        let (_working_dir, repo) = setup_repo();
+        repo.config()
+            .unwrap()
+            .set_str("branchstack.storage", "ref")
+            .unwrap();
+        let backend = open(&repo);
+
+        create_stack(&*backend, SCOPE, "review").unwrap();
+        assert_that(&repo.find_reference("refs/branch-stack/review").is_ok()).is_true();
+        assert_that(&stack_path(&*backend, SCOPE, "review").unwrap()).does_not_exist();
+        assert_that(&list_stacks(&*backend, SCOPE).unwrap())
+            .is_equal_to(vec!["default".to_string(), "review".to_string()]);
+
+        {
+            let mut stack = open_stack(&*backend, SCOPE, "review").unwrap();
+            stack.push("master");
+            stack.save().unwrap();
+        }
+        let stack = open_stack(&*backend, SCOPE, "review").unwrap();
+        assert_that(&stack.peek().map(|e| e.name))
+            .is_some()
+            .is_equal_to("master".to_string());
+        drop(stack);
+
+        delete_stack(&*backend, SCOPE, "review").unwrap();
+        assert_that(&repo.find_reference("refs/branch-stack/review").is_ok()).is_false();
+        assert_that(&stack_exists(&*backend, SCOPE, "review").unwrap()).is_false();
+    }
+
+    #[test]
+    fn bad_storage_is_an_error() {
+        let (_working_dir, repo) = setup_repo();
+        repo.config()
+            .unwrap()
+            .set_str("branchstack.storage", "cloud")
+            .unwrap();
+        let backend = open(&repo);
+        assert_that(&stack_storage(&*backend, DEFAULT_STACK))
+            .is_err()
+            .matches(|v| matches!(v, BranchStackError::InvalidStackStorage(_)));
+    }
+}
@@ -0,0 +1,91 @@
+/// # Dirty Working Trees
+///
+/// What happens to uncommitted changes when the stack switches branches is
+/// chosen by the `DirtyPolicy`. It's taken from the `--dirty` option or the
+/// `branchstack.dirty` git config setting:
+///
+/// - `carry` takes the changes along to the new branch, the way
+///   `git checkout` does. If they'd be overwritten, the switch fails. This
+///   is the default.
+/// - `abort` refuses to switch while there are any changes.
+/// - `autostash` stashes the changes before switching, and the stash is
+///   tied to the stack entry for the branch they were made on. When that
+///   entry is checked out again, the stash is re-applied and dropped.
+///
+/// Untracked and ignored files are left where they are in every case.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::backend::GitBackend;
+use crate::errors::{BranchStackError, Result};
+use crate::stack::StackEntry;
+
+/// The git config setting that chooses the dirty working tree policy.
+const DIRTY_CONFIG_KEY: &str = "branchstack.dirty";
+
+/// What to do with uncommitted changes when switching branches.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DirtyPolicy {
+    /// Refuse to switch.
+    Abort,
+    /// Take the changes along.
+    #[default]
+    Carry,
+    /// Stash the changes and re-apply them on the way back.
+    Autostash,
+}
+
+impl DirtyPolicy {
+    /// The policy set in the git config, or `Carry` if there isn't one.
+    pub fn from_config(repo: &dyn GitBackend) -> Result<DirtyPolicy> {
+        match repo.config_string(DIRTY_CONFIG_KEY)? {
+            Some(policy) => policy.parse(),
+            None => Ok(DirtyPolicy::default()),
+        }
+    }
+}
+
+impl FromStr for DirtyPolicy {
+    type Err = BranchStackError;
+
+    fn from_str(input: &str) -> Result<DirtyPolicy> {
+        match input {
+            "abort" => Ok(DirtyPolicy::Abort),
+            "carry" => Ok(DirtyPolicy::Carry),
+            "autostash" => Ok(DirtyPolicy::Autostash),
+            _ => Err(BranchStackError::InvalidDirtyPolicy(input.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for DirtyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DirtyPolicy::Abort => write!(f, "abort"),
+            DirtyPolicy::Carry => write!(f, "carry"),
+            DirtyPolicy::Autostash => write!(f, "autostash"),
+        }
+    }
+}
+
+/// Are there changes to tracked files in the index or the working tree?
+pub fn is_dirty(repo: &dyn GitBackend) -> Result<bool> {
+    repo.is_dirty()
+}
+
+/// Stash the changes that were made on `entry`, and return the id of the
+/// stash commit.
+pub fn stash_changes(repo: &dyn GitBackend, entry: &StackEntry) -> Result<String> {
+    let message = format!("git-branch-stack autostash on {}", entry);
+    repo.stash_changes(&message)
+}
+
+/// Re-apply the stash with the id `stash` and drop it, like `git stash pop`
+/// does. `entry` is what the changes were made on. If the stash can't be
+/// found, it's already been dealt with, so nothing happens. If it can't be
+/// applied cleanly, it's kept, and this returns
+/// `Err(BranchStackError::StashConflict)`.
+pub fn restore_stash(repo: &dyn GitBackend, entry: &StackEntry, stash: &str) -> Result<()> {
+    repo.pop_stash(stash)
+        .map_err(|err| BranchStackError::StashConflict(entry.to_string(), err.to_string()))
+}
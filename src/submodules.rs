@@ -0,0 +1,67 @@
+/// # Submodules
+///
+/// Checking out a branch doesn't touch the submodules in it, so they're
+/// left at whatever commits they were on. When submodules are recursed
+/// into, every initialized submodule is updated after a switch to the
+/// commit recorded on the new branch, the same as `git checkout
+/// --recurse-submodules` does. Whether to do that comes from the
+/// `--recurse-submodules` option or the `submodule.recurse` git config
+/// setting.
+///
+/// Submodules that haven't been initialized are left alone. So are ones
+/// with uncommitted changes that updating would throw away; they're
+/// reported instead.
+use std::path::PathBuf;
+
+use crate::backend::{open_backend, GitBackend};
+use crate::errors::{BranchStackError, Result};
+use crate::stack::StackEntry;
+
+/// The git config setting that turns on recursing into submodules.
+const RECURSE_CONFIG_KEY: &str = "submodule.recurse";
+
+/// Whether the git config says to recurse into submodules.
+pub fn recurse_from_config(repo: &dyn GitBackend) -> Result<bool> {
+    Ok(repo.config_bool(RECURSE_CONFIG_KEY)?.unwrap_or(false))
+}
+
+/// Update each initialized submodule to the commit recorded in the index,
+/// fetching it if it's missing. If some submodules had local changes and
+/// weren't updated, the rest are still updated, and this returns
+/// `Err(BranchStackError::SubmoduleChanges)` listing them.
+///
+/// Each submodule is opened with the same kind of backend as `repo`.
+pub fn update_submodules(repo: &dyn GitBackend) -> Result<()> {
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return Ok(()),
+    };
+    let mut skipped: Vec<PathBuf> = Vec::new();
+    for (path, target) in repo.submodules()? {
+        let submodule_dir = workdir.join(&path);
+        if !submodule_dir.join(".git").exists() {
+            continue;
+        }
+        let submodule = match open_backend(&submodule_dir, repo.kind()) {
+            Ok(submodule) => submodule,
+            Err(_) => continue,
+        };
+        if submodule.head_commit().ok() == Some(target.clone()) {
+            continue;
+        }
+        if submodule.is_dirty()? {
+            skipped.push(path);
+            continue;
+        }
+        if submodule.resolve_revision(&target).is_err() {
+            submodule.fetch()?;
+        }
+        submodule.checkout_entry(&StackEntry::commit(target), false)?;
+    }
+
+    if skipped.is_empty() {
+        Ok(())
+    } else {
+        Err(BranchStackError::SubmoduleChanges(skipped))
+    }
+}
@@ -0,0 +1,154 @@
+mod utils;
+
+use std::fs::{read_to_string, write};
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::Repository;
+use predicates::prelude::*;
+use spectral::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_dirty_abort_refuses_to_switch() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    write(basepath.join("ipsum-i"), "changed\n").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--dirty", "abort", "push", "master"])
+        .current_dir(basepath)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("you have uncommitted changes"));
+    assert_branch(&repo, "second-branch");
+}
+
+#[test]
+fn test_autostash_reapplies_changes_on_return() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+    repo.config()
+        .unwrap()
+        .set_str("branchstack.dirty", "autostash")
+        .unwrap();
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+    write(basepath.join("ipsum-ii"), "work in progress\n").unwrap();
+
+    command(basepath, &["push", "master"]);
+    assert_branch(&repo, "master");
+    assert_that(&basepath.join("ipsum-ii")).does_not_exist();
+
+    command(basepath, &["pop"]);
+    assert_branch(&repo, "second-branch");
+    assert_that(&read_to_string(basepath.join("ipsum-ii")).unwrap())
+        .is_equal_to("work in progress\n".to_string());
+
+    // the stash was dropped once it was re-applied
+    let mut repo = Repository::open(basepath).unwrap();
+    let mut stashes = 0;
+    repo.stash_foreach(|_, _, _| {
+        stashes += 1;
+        true
+    })
+    .unwrap();
+    assert_that(&stashes).is_equal_to(0);
+}
+
+#[test]
+fn test_checkout_conflicts_list_files() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+    write(basepath.join("ipsum-ii"), "work in progress\n").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "master"])
+        .current_dir(basepath)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("ipsum-ii"));
+    assert_branch(&repo, "second-branch");
+}
+
+#[test]
+fn test_force_discards_changes_after_confirmation() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+    write(basepath.join("ipsum-ii"), "work in progress\n").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "--force", "master"])
+        .current_dir(basepath)
+        .with_stdin()
+        .buffer("n\n")
+        .assert()
+        .failure();
+    assert_branch(&repo, "second-branch");
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "--force", "master"])
+        .current_dir(basepath)
+        .with_stdin()
+        .buffer("y\n")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("ipsum-ii"));
+    assert_branch(&repo, "master");
+    assert_that(&basepath.join("ipsum-ii")).does_not_exist();
+}
+
+#[test]
+fn test_force_discards_changes_with_git_backend() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+    write(basepath.join("ipsum-ii"), "work in progress\n").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--backend", "git", "push", "--force", "master"])
+        .current_dir(basepath)
+        .with_stdin()
+        .buffer("y\n")
+        .assert()
+        .success()
+        .stderr(
+            predicate::str::contains("Discard these changes?")
+                .and(predicate::str::contains("ipsum-ii")),
+        );
+    assert_branch(&repo, "master");
+    assert_that(&basepath.join("ipsum-ii")).does_not_exist();
+}
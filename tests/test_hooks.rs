@@ -0,0 +1,128 @@
+#![cfg(unix)]
+
+mod utils;
+
+use std::fs::{create_dir_all, read_to_string, set_permissions, write, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::Repository;
+use predicates::prelude::*;
+use spectral::prelude::*;
+use tempfile::tempdir;
+
+fn write_hook(repo: &Repository, script: &str) {
+    let hooks = repo.path().join("hooks");
+    create_dir_all(&hooks).unwrap();
+    let path = hooks.join("post-checkout");
+    write(&path, script).unwrap();
+    set_permissions(&path, Permissions::from_mode(0o755)).unwrap();
+}
+
+fn rev_parse(repo: &Repository, revision: &str) -> String {
+    repo.revparse_single(revision).unwrap().id().to_string()
+}
+
+#[test]
+fn test_post_checkout_runs_on_switch() {
+    let basedir = tempdir().unwrap();
+    let basepath: &Path = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+    write_hook(
+        &repo,
+        "#!/bin/sh\necho \"$@\" > \"$(git rev-parse --git-dir)/hook-args\"\n",
+    );
+
+    let second = rev_parse(&repo, "second-branch");
+    let master = rev_parse(&repo, "master");
+    command(basepath, &["push", "master"]);
+    assert_that(&read_to_string(repo.path().join("hook-args")).unwrap())
+        .is_equal_to(format!("{} {} 1\n", second, master));
+
+    command(basepath, &["pop"]);
+    assert_that(&read_to_string(repo.path().join("hook-args")).unwrap())
+        .is_equal_to(format!("{} {} 1\n", master, second));
+}
+
+#[test]
+fn test_post_checkout_failure_keeps_the_stack() {
+    let basedir = tempdir().unwrap();
+    let basepath: &Path = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    write_hook(&repo, "#!/bin/sh\nexit 1\n");
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "master"])
+        .current_dir(basepath)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("post-checkout hook failed"));
+    assert_branch(&repo, "master");
+    command(basepath, &["list"]).stdout("master\nsecond-branch\n");
+}
+
+#[test]
+fn test_git_backend_runs_hooks_once() {
+    let basedir = tempdir().unwrap();
+    let basepath: &Path = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    write_hook(
+        &repo,
+        "#!/bin/sh\necho \"$@\" >> \"$(git rev-parse --git-dir)/hook-args\"\n",
+    );
+
+    command(basepath, &["--backend", "git", "push", "master"]);
+    assert_branch(&repo, "master");
+    let hook_args = read_to_string(repo.path().join("hook-args")).unwrap();
+    assert_that(&hook_args.lines().count()).is_equal_to(1);
+    command(basepath, &["list"]).stdout("master\nsecond-branch\n");
+}
+
+#[test]
+fn test_post_checkout_runs_when_the_stash_conflicts() {
+    let basedir = tempdir().unwrap();
+    let basepath: &Path = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    write(basepath.join("ipsum-i"), "work in progress\n").unwrap();
+    command(basepath, &["--dirty", "autostash", "push", "master"]);
+    assert_branch(&repo, "master");
+
+    write_hook(
+        &repo,
+        "#!/bin/sh\necho \"$@\" > \"$(git rev-parse --git-dir)/hook-args\"\n",
+    );
+    write(basepath.join("ipsum-i"), "something else\n").unwrap();
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--dirty", "carry", "pop"])
+        .current_dir(basepath)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("couldn't be re-applied"));
+    assert_branch(&repo, "second-branch");
+    let commit = rev_parse(&repo, "second-branch");
+    assert_that(&read_to_string(repo.path().join("hook-args")).unwrap())
+        .is_equal_to(format!("{} {} 1\n", commit, commit));
+}
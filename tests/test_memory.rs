@@ -0,0 +1,52 @@
+mod utils;
+
+use utils::*;
+
+use git2::Repository;
+use git_branch_stack::actions::pop::pop_branch_stack;
+use git_branch_stack::actions::push::push_branch;
+use git_branch_stack::actions::undo::undo;
+use git_branch_stack::backend::{open_backend, BackendKind};
+use git_branch_stack::intent::{Intent, SwitchOptions};
+use git_branch_stack::journal::Journal;
+use git_branch_stack::stack::{MemoryStack, Stack};
+use spectral::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_actions_with_a_memory_stack() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+
+    // create 2nd branch
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+
+    let mut stack = MemoryStack::new();
+    let journal = Journal::in_memory();
+    let options = SwitchOptions::default();
+    let backend = open_backend(basedir.path(), BackendKind::default()).unwrap();
+
+    push_branch(&*backend, &mut stack, &journal, &options, "master").unwrap();
+    assert_branch(&repo, "master");
+    assert_that(&stack.peek().map(|e| e.name))
+        .is_some()
+        .is_equal_to("second-branch".to_string());
+
+    pop_branch_stack(&*backend, &mut stack, &journal, &options).unwrap();
+    assert_branch(&repo, "second-branch");
+    assert_that(&stack.is_empty()).is_true();
+
+    undo(&*backend, &mut stack, &journal, &options).unwrap();
+    assert_branch(&repo, "master");
+    assert_that(&stack.len()).is_equal_to(1);
+
+    // nothing was written next to the repository
+    assert_that(&repo.path().join("BRANCH_STACK")).does_not_exist();
+    assert_that(&repo.path().join("BRANCH_STACK.journal")).does_not_exist();
+    assert_that(&Intent::path(&*backend)).does_not_exist();
+}
@@ -56,5 +56,87 @@ fn test_pop() {
         .current_dir(basepath)
         .assert()
         .failure()
-        .stderr(predicate::str::contains("EmptyStack"));
+        .stderr(predicate::str::contains("empty stack"));
+}
+
+#[test]
+fn test_pop_keeps_stack_when_checkout_fails() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+
+    command(basepath, &["push", "master"]);
+    repo.find_branch("second-branch", BranchType::Local)
+        .unwrap()
+        .delete()
+        .unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .arg("pop")
+        .current_dir(basepath)
+        .assert()
+        .failure();
+
+    assert_branch(&repo, "master");
+    command(basepath, &["list"]).stdout("master\nsecond-branch\n");
+}
+
+#[test]
+fn test_pop_deletes_temp_branch() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    repo.tag_lightweight("v1", first_commit.as_object(), false)
+        .unwrap();
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+
+    command(basepath, &["push", "--temp", "v1"]);
+    assert_branch(&repo, "tmp/v1");
+    assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+
+    command(basepath, &["pop"]).stderr("deleted temporary branch tmp/v1\n");
+    assert_branch(&repo, "master");
+    assert_that(&repo.find_branch("tmp/v1", BranchType::Local).is_err()).is_true();
+
+    // a temporary branch with new work on it is kept
+    command(basepath, &["push", "--temp", "v1"]);
+    commit_random_file(basepath, &repo, "ipsum-iii", "new work").unwrap();
+    command(basepath, &["pop"]).stderr(predicate::str::contains("kept temporary branch"));
+    assert_branch(&repo, "master");
+    assert_that(&repo.find_branch("tmp/v1", BranchType::Local).is_ok()).is_true();
+}
+
+#[test]
+fn test_pop_keeps_temp_branch_only_reachable_from_autostash() {
+    let basedir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit = commit_random_file(basepath, &repo, "ipsum-i", "first commit").unwrap();
+    repo.tag_lightweight("v1", first_commit.as_object(), false)
+        .unwrap();
+    commit_random_file(basepath, &repo, "ipsum-ii", "second commit").unwrap();
+
+    command(basepath, &["push", "--temp", "v1"]);
+    let new_work = commit_random_file(basepath, &repo, "ipsum-iii", "new work")
+        .unwrap()
+        .id();
+    std::fs::write(basepath.join("ipsum-iii"), "work in progress\n").unwrap();
+
+    // the stash is made on top of the new work, but it doesn't count
+    command(basepath, &["--dirty", "autostash", "pop"])
+        .stderr(predicate::str::contains("kept temporary branch"));
+    assert_branch(&repo, "master");
+    let branch = repo.find_branch("tmp/v1", BranchType::Local).unwrap();
+    assert_that(&branch.get().target()).is_equal_to(Some(new_work));
 }
@@ -1,8 +1,13 @@
 mod utils;
 
+use std::fs::create_dir;
+use std::process::Command;
+
 use utils::*;
 
-use git2::Repository;
+use assert_cmd::prelude::*;
+use git2::{BranchType, Repository};
+use predicates::prelude::*;
 use spectral::prelude::*;
 use tempfile::tempdir;
 
@@ -24,3 +29,290 @@ fn test_push() {
     assert_branch(&repo, "master");
     assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
 }
+
+#[test]
+fn test_push_reports_save_errors() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+
+    // A directory in the way of the temporary file makes the save fail.
+    create_dir(repo.path().join("BRANCH_STACK.tmp")).unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "master"])
+        .current_dir(basedir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Is a directory"));
+}
+
+#[test]
+fn test_push_from_detached_head() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    let first_id = first_commit.id();
+    repo.set_head_detached(first_id).unwrap();
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+        .unwrap();
+
+    command(&basedir, &["push", "master"]);
+    assert_branch(&repo, "master");
+    command(&basedir, &["list"]).stdout(format!("master\n{}\n", first_id));
+
+    command(&basedir, &["pop"]);
+    assert_that(&repo.head_detached().unwrap()).is_true();
+    assert_that(&repo.head().unwrap().target())
+        .is_some()
+        .is_equal_to(first_id);
+    assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+}
+
+#[test]
+fn test_push_tag_detaches_head() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    repo.tag_lightweight("v1.0", first_commit.as_object(), false)
+        .unwrap();
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+
+    command(&basedir, &["push", "v1.0"]);
+    assert_that(&repo.head_detached().unwrap()).is_true();
+    assert_that(&repo.head().unwrap().target())
+        .is_some()
+        .is_equal_to(first_commit.id());
+    assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+
+    command(&basedir, &["pop"]);
+    assert_branch(&repo, "master");
+}
+
+#[test]
+fn test_push_creates_tracking_branch() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    repo.remote("origin", "https://example.com/repo.git")
+        .unwrap();
+    repo.reference(
+        "refs/remotes/origin/feature-x",
+        first_commit.id(),
+        false,
+        "fetch",
+    )
+    .unwrap();
+
+    command(&basedir, &["push", "feature-x"])
+        .stdout("branch 'feature-x' set up to track 'origin/feature-x'.\n");
+
+    assert_branch(&repo, "feature-x");
+    let branch = repo.find_branch("feature-x", BranchType::Local).unwrap();
+    assert_that(&branch.upstream().unwrap().name().unwrap())
+        .is_some()
+        .is_equal_to("origin/feature-x");
+    assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+    command(&basedir, &["list"]).stdout("feature-x\nmaster\n");
+}
+
+#[test]
+fn test_push_create_branch() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    let second_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+
+    command(&basedir, &["push", "-c", "from-head"]);
+    assert_branch(&repo, "from-head");
+    assert_that(&repo.head().unwrap().target())
+        .is_some()
+        .is_equal_to(second_commit.id());
+
+    command(&basedir, &["push", "-b", "from-first", "master~1"]);
+    assert_branch(&repo, "from-first");
+    assert_that(&repo.head().unwrap().target())
+        .is_some()
+        .is_equal_to(first_commit.id());
+    assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+    command(&basedir, &["list"]).stdout("from-first\nfrom-head\nmaster\n");
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "-c", "master"])
+        .current_dir(basedir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("branch already exists"));
+}
+
+#[test]
+fn test_push_leaves_previous_branch_for_git() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+
+    command(&basedir, &["push", "master"]);
+    assert_branch(&repo, "master");
+
+    // git finds the previous branch in the reflog
+    Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "@{-1}"])
+        .current_dir(basedir.path())
+        .assert()
+        .success()
+        .stdout("second-branch\n");
+    Command::new("git")
+        .args(["checkout", "-"])
+        .current_dir(basedir.path())
+        .assert()
+        .success();
+    assert_branch(&repo, "second-branch");
+}
+
+#[test]
+fn test_push_revision_names_it_in_the_reflog() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+    commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+
+    command(&basedir, &["push", "HEAD~1"]);
+    assert_that(&repo.head_detached().unwrap()).is_true();
+    let reflog = repo.reflog("HEAD").unwrap();
+    assert_that(&reflog.get(0).unwrap().message().unwrap())
+        .is_equal_to("checkout: moving from master to HEAD~1");
+}
+
+#[test]
+fn test_push_with_gix_backend() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+
+    if cfg!(feature = "gix") {
+        command(&basedir, &["--backend", "gix", "push", "master"]);
+        assert_branch(&repo, "master");
+        assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+        command(&basedir, &["--backend", "gix", "pop"]);
+        assert_branch(&repo, "second-branch");
+        assert_that(&basedir.path().join("ipsum-ii")).exists();
+    } else {
+        Command::cargo_bin(env!("CARGO_PKG_NAME"))
+            .unwrap()
+            .args(["--backend", "gix", "push", "master"])
+            .current_dir(basedir.path())
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("backend isn't available"));
+        assert_branch(&repo, "second-branch");
+    }
+}
+
+/// Create the branch `caf\xe9`, whose name isn't valid UTF-8, at `commit`.
+/// git2 only takes branch names as `&str`, so the ref file is written
+/// directly.
+#[cfg(unix)]
+fn create_non_utf8_branch(repo: &Repository, commit: &git2::Commit<'_>) -> &'static [u8] {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let branch_name = b"caf\xe9";
+    let refname = [b"refs/heads/".as_ref(), branch_name].concat();
+    std::fs::write(
+        repo.path().join(OsStr::from_bytes(&refname)),
+        format!("{}\n", commit.id()),
+    )
+    .unwrap();
+    branch_name
+}
+
+#[cfg(unix)]
+#[test]
+fn test_push_non_utf8_branch() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    let branch_name = create_non_utf8_branch(&repo, &first_commit);
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .arg("push")
+        .arg(OsStr::from_bytes(branch_name))
+        .current_dir(basedir.path())
+        .assert()
+        .success();
+
+    let head = repo.find_reference("HEAD").unwrap();
+    assert_that(&head.symbolic_target_bytes())
+        .is_some()
+        .is_equal_to(&b"refs/heads/caf\xe9"[..]);
+    assert_that(&basedir.path().join("ipsum-ii")).does_not_exist();
+
+    command(&basedir, &["pop"]);
+    assert_branch(&repo, "second-branch");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_push_from_non_utf8_branch() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    let branch_name = create_non_utf8_branch(&repo, &first_commit);
+    std::fs::write(
+        repo.path().join("HEAD"),
+        [b"ref: refs/heads/".as_ref(), branch_name, b"\n"].concat(),
+    )
+    .unwrap();
+
+    command(&basedir, &["push", "master"]);
+    assert_branch(&repo, "master");
+    command(&basedir, &["list"]).stdout(predicate::str::contains("caf\u{fffd}"));
+
+    command(&basedir, &["pop"]);
+    let head = repo.find_reference("HEAD").unwrap();
+    assert_that(&head.symbolic_target_bytes())
+        .is_some()
+        .is_equal_to(&b"refs/heads/caf\xe9"[..]);
+}
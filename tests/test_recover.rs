@@ -0,0 +1,121 @@
+mod utils;
+
+use std::fs::{create_dir, read_to_string, remove_dir, write, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::Repository;
+use predicates::prelude::*;
+use spectral::prelude::*;
+use tempfile::{tempdir, TempDir};
+
+/// Set up a repository where `push master` is interrupted after checking
+/// out `master` but before saving the stack.
+fn setup_interrupted_push() -> (TempDir, Repository) {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    {
+        let first_commit =
+            commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+        checkout_new_branch(&repo, &first_commit, "second-branch");
+        commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    }
+
+    // A directory in the way of the temporary file makes the save fail.
+    create_dir(repo.path().join("BRANCH_STACK.tmp")).unwrap();
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "master"])
+        .current_dir(basedir.path())
+        .assert()
+        .failure();
+    remove_dir(repo.path().join("BRANCH_STACK.tmp")).unwrap();
+
+    (basedir, repo)
+}
+
+fn assert_command_fails<P: AsRef<Path>>(path: P, args: &[&str], message: &str) {
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(args)
+        .current_dir(path.as_ref())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(message));
+}
+
+#[test]
+fn test_interrupted_operation_blocks_other_commands() {
+    let (basedir, repo) = setup_interrupted_push();
+    assert_that(&repo.path().join("BRANCH_STACK.intent")).exists();
+    assert_command_fails(&basedir, &["pop"], "was interrupted");
+    assert_command_fails(&basedir, &["push", "second-branch"], "was interrupted");
+}
+
+#[test]
+fn test_continue_finishes_the_operation() {
+    let (basedir, repo) = setup_interrupted_push();
+
+    command(&basedir, &["continue"]).stdout("master\n");
+    assert_branch(&repo, "master");
+    command(&basedir, &["list"]).stdout("master\nsecond-branch\n");
+
+    // and it can be undone like any other push
+    command(&basedir, &["undo"]);
+    assert_branch(&repo, "second-branch");
+    assert_command_fails(&basedir, &["continue"], "no interrupted operation");
+}
+
+#[test]
+fn test_abort_rolls_back_the_operation() {
+    let (basedir, repo) = setup_interrupted_push();
+
+    command(&basedir, &["abort"]).stdout("second-branch\n");
+    assert_branch(&repo, "second-branch");
+    command(&basedir, &["list"]).stdout("second-branch\n");
+    assert_command_fails(&basedir, &["abort"], "no interrupted operation");
+}
+
+#[test]
+fn test_continue_and_abort_take_over_a_stale_lock() {
+    for action in &["continue", "abort"] {
+        let (basedir, repo) = setup_interrupted_push();
+        // A process that's killed leaves its lock behind.
+        write(repo.path().join("BRANCH_STACK.lock"), "").unwrap();
+
+        command(&basedir, &[action]);
+        assert_that(&repo.path().join("BRANCH_STACK.lock")).does_not_exist();
+        assert_that(&repo.path().join("BRANCH_STACK.intent")).does_not_exist();
+        command(&basedir, &["list"]);
+    }
+}
+
+#[test]
+fn test_continue_records_the_operation_once() {
+    let (basedir, repo) = setup_interrupted_push();
+    // Interrupt it after the journal was written, but before the intent
+    // was removed.
+    let intent: serde_json::Value =
+        serde_json::from_str(&read_to_string(repo.path().join("BRANCH_STACK.intent")).unwrap())
+            .unwrap();
+    let mut journal = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(repo.path().join("BRANCH_STACK.journal"))
+        .unwrap();
+    writeln!(journal, "{}", intent["record"]).unwrap();
+
+    command(&basedir, &["continue"]).stdout("master\n");
+    let journal = read_to_string(repo.path().join("BRANCH_STACK.journal")).unwrap();
+    assert_that(&journal.lines().count()).is_equal_to(1);
+
+    command(&basedir, &["undo"]);
+    assert_branch(&repo, "second-branch");
+    assert_command_fails(&basedir, &["undo"], "nothing to undo");
+}
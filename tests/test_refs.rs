@@ -0,0 +1,82 @@
+mod utils;
+
+use std::path::Path;
+use std::process::Command;
+
+use utils::*;
+
+use git2::Repository;
+use spectral::prelude::*;
+use tempfile::tempdir;
+
+fn git<P: AsRef<Path>>(path: P, args: &[&str]) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(path.as_ref())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_ref_storage_can_be_pushed_and_fetched() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+
+    // create 2nd branch
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    // create 3rd branch
+    checkout_new_branch(&repo, &first_commit, "third-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-iii", "third commit").unwrap();
+
+    repo.config()
+        .unwrap()
+        .set_str("branchstack.storage", "ref")
+        .unwrap();
+    command(&basedir, &["push", "master"]);
+    command(&basedir, &["push", "second-branch"]);
+    assert_branch(&repo, "second-branch");
+    command(&basedir, &["list"]).stdout("second-branch\nmaster\nthird-branch\n");
+    assert_that(&basedir.path().join(".git").join("BRANCH_STACK")).does_not_exist();
+
+    // every save is a commit on the stack's ref
+    let stack_ref = repo.find_reference("refs/branch-stack/default").unwrap();
+    let mut revwalk = repo.revwalk().unwrap();
+    revwalk.push(stack_ref.target().unwrap()).unwrap();
+    assert_that(&revwalk.count()).is_equal_to(2);
+
+    // push everything to a bare remote
+    let remote_dir = tempdir().unwrap();
+    Repository::init_bare(remote_dir.path()).unwrap();
+    let remote = remote_dir.path().to_str().unwrap();
+    git(&basedir, &["push", remote, "--all"]);
+    git(
+        &basedir,
+        &["push", remote, "refs/branch-stack/*:refs/branch-stack/*"],
+    );
+
+    // and bring the stack into a new clone
+    let clone_dir = tempdir().unwrap();
+    git(
+        &clone_dir,
+        &["clone", "--quiet", "--branch", "master", remote, "clone"],
+    );
+    let clone_path = clone_dir.path().join("clone");
+    git(
+        &clone_path,
+        &[
+            "fetch",
+            "--quiet",
+            "origin",
+            "refs/branch-stack/*:refs/branch-stack/*",
+        ],
+    );
+    git(&clone_path, &["config", "branchstack.storage", "ref"]);
+    command(&clone_path, &["list", "-v"])
+        .stdout("# stack default (ref: refs/branch-stack/default)\nmaster\nmaster\nthird-branch\n");
+}
@@ -1,6 +1,6 @@
 mod utils;
 
-use git_branch_stack::git::change_branch;
+use git_branch_stack::backend::{open_backend, BackendKind};
 use utils::*;
 
 use git2::Repository;
@@ -22,7 +22,10 @@ fn test_rotate() {
     checkout_new_branch(&repo, &first_commit, "third-branch");
     commit_random_file(basedir.path(), &repo, "ipsum-iii", "third commit").unwrap();
 
-    change_branch(&repo, "master").unwrap();
+    open_backend(basedir.path(), BackendKind::default())
+        .unwrap()
+        .change_branch("master")
+        .unwrap();
     command(&basedir, &["push", "second-branch"]);
     command(&basedir, &["push", "third-branch"]);
 
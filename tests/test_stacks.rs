@@ -0,0 +1,65 @@
+mod utils;
+
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::Repository;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_named_stacks() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+
+    // create 2nd branch
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    // create 3rd branch
+    checkout_new_branch(&repo, &first_commit, "third-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-iii", "third commit").unwrap();
+
+    command(&basedir, &["stacks", "list"]).stdout("* default\n");
+    command(&basedir, &["stacks", "create", "review"]);
+    command(&basedir, &["stacks", "list"]).stdout("* default\n  review\n");
+
+    // the stack can be chosen on the command line
+    command(&basedir, &["--stack", "review", "push", "master"]);
+    assert_branch(&repo, "master");
+    command(&basedir, &["list", "--stack", "review"]).stdout("master\nthird-branch\n");
+    command(&basedir, &["list"]).stdout("master\n");
+
+    // or remembered as the current stack
+    command(&basedir, &["push", "second-branch"]);
+    command(&basedir, &["stacks", "switch", "review"]);
+    command(&basedir, &["stacks"]).stdout("  default\n* review\n");
+    command(&basedir, &["list"]).stdout("second-branch\nthird-branch\n");
+    command(&basedir, &["pop"]).stdout("third-branch\n");
+    assert_branch(&repo, "third-branch");
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["stacks", "delete", "review"])
+        .current_dir(basedir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is the current stack"));
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--stack", "missing", "list"])
+        .current_dir(basedir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no such stack"));
+
+    command(&basedir, &["stacks", "switch", "default"]);
+    command(&basedir, &["stacks", "delete", "review"]);
+    command(&basedir, &["stacks", "list"]).stdout("* default\n");
+    command(&basedir, &["list"]).stdout("third-branch\nmaster\n");
+}
@@ -0,0 +1,111 @@
+mod utils;
+
+use std::fs::write;
+use std::path::Path;
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::{Oid, Repository};
+use predicates::prelude::*;
+use spectral::prelude::*;
+use tempfile::tempdir;
+
+fn git(dir: &Path, args: &[&str]) {
+    Command::new("git")
+        .args([
+            "-c",
+            "user.name=Trillian McMillan",
+            "-c",
+            "user.email=tmcmilla@heartofgold.ship",
+            "-c",
+            "protocol.file.allow=always",
+        ])
+        .args(args)
+        .current_dir(dir)
+        .assert()
+        .success();
+}
+
+fn submodule_head(basepath: &Path) -> Oid {
+    let submodule = Repository::open(basepath.join("sub")).unwrap();
+    let head = submodule.head().unwrap();
+    head.target().unwrap()
+}
+
+/// Set up a repository whose `sub` submodule is at its second commit on
+/// master and at its first commit on `old-sub`. Returns the ids of those
+/// two commits.
+fn setup_submodule(basepath: &Path, subpath: &Path) -> (Oid, Oid) {
+    let sub_repo = Repository::init(subpath).unwrap();
+    make_initial_commit(&sub_repo);
+    let first = commit_random_file(subpath, &sub_repo, "ipsum-i", "first commit")
+        .unwrap()
+        .id();
+    let second = commit_random_file(subpath, &sub_repo, "ipsum-ii", "second commit")
+        .unwrap()
+        .id();
+
+    let repo = Repository::init(basepath).unwrap();
+    make_initial_commit(&repo);
+    git(
+        basepath,
+        &["submodule", "add", subpath.to_str().unwrap(), "sub"],
+    );
+    git(basepath, &["commit", "-m", "add submodule"]);
+    git(basepath, &["checkout", "-b", "old-sub"]);
+    git(&basepath.join("sub"), &["checkout", &first.to_string()]);
+    git(basepath, &["commit", "-am", "use the old submodule"]);
+    git(basepath, &["checkout", "--recurse-submodules", "master"]);
+
+    (first, second)
+}
+
+#[test]
+fn test_recurse_submodules_updates_them() {
+    let basedir = tempdir().unwrap();
+    let subdir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let (first, second) = setup_submodule(basepath, subdir.path());
+    let repo = Repository::open(basepath).unwrap();
+    assert_that(&submodule_head(basepath)).is_equal_to(second);
+
+    // without recursing, the submodule is left where it is
+    command(basepath, &["push", "old-sub"]);
+    assert_branch(&repo, "old-sub");
+    assert_that(&submodule_head(basepath)).is_equal_to(second);
+    command(basepath, &["pop"]);
+
+    command(basepath, &["--recurse-submodules", "push", "old-sub"]);
+    assert_branch(&repo, "old-sub");
+    assert_that(&submodule_head(basepath)).is_equal_to(first);
+
+    repo.config()
+        .unwrap()
+        .set_bool("submodule.recurse", true)
+        .unwrap();
+    command(basepath, &["pop"]);
+    assert_branch(&repo, "master");
+    assert_that(&submodule_head(basepath)).is_equal_to(second);
+}
+
+#[test]
+fn test_recurse_submodules_reports_local_changes() {
+    let basedir = tempdir().unwrap();
+    let subdir = tempdir().unwrap();
+    let basepath = basedir.path();
+    let (_first, second) = setup_submodule(basepath, subdir.path());
+    let repo = Repository::open(basepath).unwrap();
+    write(basepath.join("sub").join("ipsum-i"), "work in progress\n").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["--recurse-submodules", "push", "old-sub"])
+        .current_dir(basepath)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("local changes").and(predicate::str::contains("sub")));
+    assert_branch(&repo, "old-sub");
+    assert_that(&submodule_head(basepath)).is_equal_to(second);
+}
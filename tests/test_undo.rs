@@ -0,0 +1,57 @@
+mod utils;
+
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::Repository;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_undo_redo() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+
+    // create 2nd branch
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    // create 3rd branch
+    checkout_new_branch(&repo, &first_commit, "third-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-iii", "third commit").unwrap();
+
+    command(&basedir, &["push", "master"]);
+    command(&basedir, &["push", "second-branch"]);
+    command(&basedir, &["pop"]);
+    command(&basedir, &["list"]).stdout("master\nthird-branch\n");
+
+    // undo the pop
+    command(&basedir, &["undo"]);
+    assert_branch(&repo, "second-branch");
+    command(&basedir, &["list"]).stdout("second-branch\nmaster\nthird-branch\n");
+
+    // undo the second push
+    command(&basedir, &["undo"]);
+    assert_branch(&repo, "master");
+    command(&basedir, &["list"]).stdout("master\nthird-branch\n");
+
+    // redo both
+    command(&basedir, &["redo"]);
+    assert_branch(&repo, "second-branch");
+    command(&basedir, &["redo"]);
+    assert_branch(&repo, "master");
+    command(&basedir, &["list"]).stdout("master\nthird-branch\n");
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .arg("redo")
+        .current_dir(basedir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("nothing to redo"));
+}
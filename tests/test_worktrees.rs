@@ -0,0 +1,154 @@
+mod utils;
+
+use std::process::Command;
+
+use utils::*;
+
+use assert_cmd::prelude::*;
+use git2::Repository;
+use predicates::prelude::*;
+use tempfile::tempdir;
+
+#[test]
+fn test_worktree_and_shared_scopes() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+
+    // create 2nd branch
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-ii", "second commit").unwrap();
+    // create 3rd branch
+    checkout_new_branch(&repo, &first_commit, "third-branch");
+    commit_random_file(basedir.path(), &repo, "ipsum-iii", "third commit").unwrap();
+
+    // add a linked worktree for the 2nd branch
+    let worktree_dir = tempdir().unwrap();
+    let worktree_path = worktree_dir.path().join("second");
+    let status = Command::new("git")
+        .args(["worktree", "add"])
+        .arg(&worktree_path)
+        .arg("second-branch")
+        .current_dir(basedir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let worktree = Repository::open(&worktree_path).unwrap();
+
+    // by default, each worktree has its own stack
+    command(&worktree_path, &["push", "master"]);
+    assert_branch(&worktree, "master");
+    command(&worktree_path, &["list"]).stdout("master\nsecond-branch\n");
+    command(&basedir, &["list"]).stdout("third-branch\n");
+    command(&worktree_path, &["list", "-v"])
+        .stdout(predicate::str::starts_with("# stack default (worktree: "));
+
+    // the shared scope is seen by every worktree
+    command(
+        &worktree_path,
+        &["--scope", "shared", "push", "second-branch"],
+    );
+    assert_branch(&worktree, "second-branch");
+    command(&worktree_path, &["--scope", "shared", "list"]).stdout("second-branch\nmaster\n");
+    // the main worktree's own git directory is the shared one
+    command(&basedir, &["list"]).stdout("third-branch\nmaster\n");
+
+    // and it can be chosen in the config
+    repo.config()
+        .unwrap()
+        .set_str("branchstack.scope", "shared")
+        .unwrap();
+    command(&worktree_path, &["list"]).stdout("second-branch\nmaster\n");
+    command(&worktree_path, &["list", "--verbose"])
+        .stdout(predicate::str::starts_with("# stack default (shared: "));
+    // the worktree's own stack is still there underneath
+    command(&worktree_path, &["--scope", "worktree", "list"])
+        .stdout("second-branch\nsecond-branch\n");
+}
+
+#[test]
+fn test_switching_stacks_in_one_worktree_leaves_others_alone() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    checkout_new_branch(&repo, &first_commit, "third-branch");
+
+    let worktree_dir = tempdir().unwrap();
+    let worktree_path = worktree_dir.path().join("second");
+    let status = Command::new("git")
+        .args(["worktree", "add"])
+        .arg(&worktree_path)
+        .arg("second-branch")
+        .current_dir(basedir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // the stack is only created in the linked worktree
+    command(&worktree_path, &["stacks", "create", "review"]);
+    command(&worktree_path, &["stacks", "switch", "review"]);
+    command(&worktree_path, &["list", "-v"]).stdout(predicate::str::starts_with("# stack review "));
+
+    // so the main worktree keeps using its default stack
+    command(&basedir, &["push", "master"]);
+    assert_branch(&repo, "master");
+    command(&basedir, &["list", "-v"]).stdout(predicate::str::starts_with("# stack default "));
+    command(&basedir, &["stacks"]).stdout("* default\n");
+}
+
+#[test]
+fn test_refuses_branch_checked_out_in_another_worktree() {
+    let basedir = tempdir().unwrap();
+    let repo = Repository::init(basedir.path()).unwrap();
+    make_initial_commit(&repo);
+
+    let first_commit =
+        commit_random_file(basedir.path(), &repo, "ipsum-i", "first commit").unwrap();
+    checkout_new_branch(&repo, &first_commit, "second-branch");
+    checkout_new_branch(&repo, &first_commit, "third-branch");
+
+    let worktree_dir = tempdir().unwrap();
+    let worktree_path = worktree_dir.path().join("second");
+    let status = Command::new("git")
+        .args(["worktree", "add"])
+        .arg(&worktree_path)
+        .arg("second-branch")
+        .current_dir(basedir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let worktree = Repository::open(&worktree_path).unwrap();
+
+    // the main worktree can't take the linked worktree's branch
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "second-branch"])
+        .current_dir(basedir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is already checked out at"));
+    assert_branch(&repo, "third-branch");
+
+    // or the other way around
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args(["push", "third-branch"])
+        .current_dir(&worktree_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is already checked out at"));
+    assert_branch(&worktree, "second-branch");
+
+    command(
+        basedir.path(),
+        &["push", "--ignore-other-worktrees", "second-branch"],
+    );
+    assert_branch(&repo, "second-branch");
+}
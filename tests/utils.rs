@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
@@ -36,7 +38,7 @@ pub fn commit_random_file<'a>(
         writeln!(file, "{}", lipsum(75)).unwrap();
     }
 
-    index.add_path(&Path::new(filename)).unwrap();
+    index.add_path(Path::new(filename)).unwrap();
     let index_oid = index.write_tree().unwrap();
     let index_tree = repo.find_tree(index_oid).unwrap();
 
@@ -61,7 +63,7 @@ pub fn commit_random_file<'a>(
 }
 
 pub fn checkout_new_branch<'a>(repo: &Repository, commit: &Commit<'a>, branch_name: &str) {
-    repo.branch(branch_name, &commit, false).unwrap();
+    repo.branch(branch_name, commit, false).unwrap();
     let refname = format!("refs/heads/{}", branch_name);
     repo.set_head(&refname).unwrap();
 